@@ -0,0 +1,311 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitBool, LitStr, Result, Type};
+
+// Implements `serde_amp::Command` for a struct, which is the command's
+// arguments:
+//
+//     #[derive(AmpCommand, Deserialize, Serialize)]
+//     #[amp(response = SumResponse)]
+//     struct Sum {
+//         a: i64,
+//         b: i64,
+//     }
+//
+// As in Twisted, the command's name is the name of the type, unless given
+// with `name = "..."`. The declared errors are given with `errors = ...`, a
+// type implementing `CommandError`, and `requires_answer = false` marks a
+// command that is never answered.
+#[proc_macro_derive(AmpCommand, attributes(amp))]
+pub fn derive_amp_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    amp_command(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+// Implements `serde_amp::CommandError` for an enum of the errors a command
+// declares. Each variant is sent with its `code = "..."`, or its name when
+// there isn't one, and `fatal` marks the errors that close the connection,
+// like Twisted's `fatalErrors`. A variant may hold a single field, which is
+// built from the error's description with `From<&str>`.
+#[proc_macro_derive(AmpError, attributes(amp))]
+pub fn derive_amp_error(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    amp_error(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn amp_command(input: &DeriveInput) -> Result<TokenStream2> {
+    if !matches!(input.data, Data::Struct(_)) {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "AmpCommand can only be derived for structs",
+        ));
+    }
+
+    let ident = &input.ident;
+    let mut name = LitStr::new(&ident.to_string(), ident.span());
+    let mut response: Option<Type> = None;
+    let mut errors: Option<Type> = None;
+    let mut requires_answer: Option<LitBool> = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("amp"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse()?;
+            } else if meta.path.is_ident("response") {
+                response = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("errors") {
+                errors = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("requires_answer") {
+                requires_answer = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unknown amp command attribute"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let response = match response {
+        Some(response) => response,
+        None => {
+            return Err(Error::new_spanned(
+                ident,
+                "AmpCommand needs a response type, e.g. #[amp(response = SumResponse)]",
+            ))
+        }
+    };
+    let errors = match errors {
+        Some(errors) => quote!(#errors),
+        None => quote!(::std::convert::Infallible),
+    };
+    let requires_answer =
+        requires_answer.map(|value| quote!(const REQUIRES_ANSWER: bool = #value;));
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::serde_amp::Command for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+            #requires_answer
+            type Arguments = Self;
+            type Response = #response;
+            type Error = #errors;
+        }
+    })
+}
+
+fn amp_error(input: &DeriveInput) -> Result<TokenStream2> {
+    let variants = match &input.data {
+        Data::Enum(data) => &data.variants,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "AmpError can only be derived for enums",
+            ))
+        }
+    };
+
+    let mut codes: Vec<LitStr> = vec![];
+    let mut code_arms = vec![];
+    let mut from_code_arms = vec![];
+    let mut fatal_arms = vec![];
+    for variant in variants {
+        let ident = &variant.ident;
+        let mut code = LitStr::new(&ident.to_string(), ident.span());
+        let mut fatal = false;
+        for attr in variant
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("amp"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("code") {
+                    code = meta.value()?.parse()?;
+                } else if meta.path.is_ident("fatal") {
+                    fatal = true;
+                } else {
+                    return Err(meta.error("unknown amp error attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        if codes.iter().any(|other| other.value() == code.value()) {
+            return Err(Error::new_spanned(&code, "duplicate error code"));
+        }
+
+        let (pattern, value) = match &variant.fields {
+            Fields::Unit => (quote!(Self::#ident), quote!(Self::#ident)),
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => (
+                quote!(Self::#ident(..)),
+                quote!(Self::#ident(::std::convert::From::from(description))),
+            ),
+            _ => {
+                return Err(Error::new_spanned(
+                    variant,
+                    "AmpError variants must be unit variants or hold a single field",
+                ))
+            }
+        };
+        code_arms.push(quote!(#pattern => #code,));
+        from_code_arms.push(quote!(#code => ::std::option::Option::Some(#value),));
+        if fatal {
+            fatal_arms.push(pattern);
+        }
+        codes.push(code);
+    }
+
+    let is_fatal = if fatal_arms.is_empty() {
+        None
+    } else {
+        Some(quote! {
+            fn is_fatal(&self) -> bool {
+                ::core::matches!(*self, #(#fatal_arms)|*)
+            }
+        })
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::serde_amp::CommandError for #ident #ty_generics #where_clause {
+            fn code(&self) -> &str {
+                match *self {
+                    #(#code_arms)*
+                }
+            }
+
+            fn from_code(code: &str, description: &str) -> ::std::option::Option<Self> {
+                match code {
+                    #(#from_code_arms)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+
+            #is_fatal
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use syn::parse_quote;
+
+    use super::*;
+
+    #[test]
+    fn test_amp_command() {
+        let input: DeriveInput = parse_quote! {
+            #[amp(name = "Add", response = SumResponse, requires_answer = false)]
+            struct Sum {
+                a: i64,
+                b: i64,
+            }
+        };
+        let output = amp_command(&input).unwrap().to_string();
+        assert!(output.contains(
+            &quote!(
+                const NAME: &'static str = "Add";
+            )
+            .to_string()
+        ));
+        assert!(output.contains(
+            &quote!(
+                const REQUIRES_ANSWER: bool = false;
+            )
+            .to_string()
+        ));
+        assert!(output.contains(
+            &quote!(
+                type Error = ::std::convert::Infallible;
+            )
+            .to_string()
+        ));
+    }
+
+    #[test]
+    fn test_amp_command_not_a_struct() {
+        let input: DeriveInput = parse_quote! {
+            #[amp(response = SumResponse)]
+            enum Sum {
+                A,
+            }
+        };
+        let error = amp_command(&input).unwrap_err();
+        assert_eq!(
+            "AmpCommand can only be derived for structs",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_amp_command_missing_response() {
+        let input: DeriveInput = parse_quote! {
+            #[amp(name = "Sum")]
+            struct Sum {
+                a: i64,
+            }
+        };
+        let error = amp_command(&input).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("AmpCommand needs a response type"));
+    }
+
+    #[test]
+    fn test_amp_error() {
+        let input: DeriveInput = parse_quote! {
+            enum DivideError {
+                #[amp(code = "ZERO_DIVISION")]
+                ZeroDivision,
+                #[amp(fatal)]
+                Overflow(String),
+            }
+        };
+        let output = amp_error(&input).unwrap().to_string();
+        assert!(output.contains(&quote!(Self::ZeroDivision => "ZERO_DIVISION",).to_string()));
+        assert!(output.contains(&quote!(Self::Overflow(..) => "Overflow",).to_string()));
+        assert!(output.contains(&quote!(::core::matches!(*self, Self::Overflow(..))).to_string()));
+    }
+
+    #[test]
+    fn test_amp_error_duplicate_code() {
+        let input: DeriveInput = parse_quote! {
+            enum DivideError {
+                #[amp(code = "Overflow")]
+                ZeroDivision,
+                Overflow,
+            }
+        };
+        let error = amp_error(&input).unwrap_err();
+        assert_eq!("duplicate error code", error.to_string());
+    }
+
+    #[test]
+    fn test_amp_error_multiple_fields() {
+        let input: DeriveInput = parse_quote! {
+            enum DivideError {
+                Overflow(String, u32),
+            }
+        };
+        let error = amp_error(&input).unwrap_err();
+        assert_eq!(
+            "AmpError variants must be unit variants or hold a single field",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_amp_error_not_an_enum() {
+        let input: DeriveInput = parse_quote! {
+            struct DivideError;
+        };
+        let error = amp_error(&input).unwrap_err();
+        assert_eq!("AmpError can only be derived for enums", error.to_string());
+    }
+}
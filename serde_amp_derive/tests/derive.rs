@@ -0,0 +1,40 @@
+use std::fmt;
+
+use serde_amp::{AmpError, CommandError};
+
+// The generated code must not pick up a macro of the user's that happens to
+// share a name with one from the standard library.
+#[allow(unused_macros)]
+macro_rules! matches {
+    ($($tokens:tt)*) => {
+        false
+    };
+}
+
+#[derive(AmpError, Debug, PartialEq)]
+enum DivideError {
+    #[amp(code = "ZERO_DIVISION")]
+    ZeroDivision,
+    #[amp(fatal)]
+    Overflow(String),
+}
+
+impl fmt::Display for DivideError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DivideError::ZeroDivision => formatter.write_str("division by zero"),
+            DivideError::Overflow(description) => formatter.write_str(description),
+        }
+    }
+}
+
+#[test]
+fn test_derive_amp_error() {
+    assert_eq!("ZERO_DIVISION", DivideError::ZeroDivision.code());
+    assert!(!DivideError::ZeroDivision.is_fatal());
+
+    let error = DivideError::Overflow("too big".to_string());
+    assert_eq!("Overflow", error.code());
+    assert!(error.is_fatal());
+    assert_eq!(Some(error), DivideError::from_code("Overflow", "too big"));
+}
@@ -0,0 +1,425 @@
+use std::fmt;
+use std::str;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::error::{Error, ErrorKind, Result};
+
+// A box whose keys aren't known up front, for reading, modifying and
+// forwarding boxes without a schema. Keys and values are kept as the byte
+// strings they are on the wire, in the order they were read. AMP doesn't
+// forbid repeating a key, so neither does `AmpBox`.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct AmpBox {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl AmpBox {
+    pub fn new() -> Self {
+        AmpBox { entries: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key<K>(&self, key: K) -> bool
+    where
+        K: AsRef<[u8]>,
+    {
+        self.get(key).is_some()
+    }
+
+    // The value of the first entry with the given key.
+    pub fn get<K>(&self, key: K) -> Option<&[u8]>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.get_all(key).next()
+    }
+
+    pub fn get_all<K>(&self, key: K) -> impl Iterator<Item = &[u8]>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k[..] == *key.as_ref())
+            .map(|(_, v)| &v[..])
+    }
+
+    // Like `get`, for values holding text. A value that isn't UTF-8 is an
+    // `ErrorKind::BadData` error, rather than being mistaken for a missing key.
+    pub fn get_str<K>(&self, key: K) -> Result<Option<&str>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        match self.get(key) {
+            Some(value) => match str::from_utf8(value) {
+                Ok(string) => Ok(Some(string)),
+                Err(_) => Err(bad_data(key, "a UTF-8 string", value)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Like `get`, for values holding an AMP `Integer`.
+    pub fn get_int<K>(&self, key: K) -> Result<Option<i64>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        match self.get(key) {
+            Some(value) => match str::from_utf8(value).ok().and_then(|s| s.parse().ok()) {
+                Some(integer) => Ok(Some(integer)),
+                None => Err(bad_data(key, "i64", value)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Sets the value of a key, replacing the first entry with that key in
+    // place and removing any others, or adding a new entry at the end.
+    // Returns the value that was replaced.
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<Vec<u8>>
+    where
+        K: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        let key = key.into();
+        let mut value = Some(value.into());
+        let mut old = None;
+        self.entries.retain_mut(|(k, v)| {
+            if *k != key {
+                return true;
+            }
+            match value.take() {
+                Some(value) => {
+                    old = Some(std::mem::replace(v, value));
+                    true
+                }
+                None => false,
+            }
+        });
+        if let Some(value) = value {
+            self.entries.push((key, value));
+        }
+        old
+    }
+
+    // Adds an entry at the end, even if the key is already present.
+    pub fn append<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        self.entries.push((key.into(), value.into()));
+    }
+
+    // Removes every entry with the given key, returning the first value.
+    pub fn remove<K>(&mut self, key: K) -> Option<Vec<u8>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        let mut removed = None;
+        self.entries.retain_mut(|(k, v)| {
+            if k[..] != *key {
+                return true;
+            }
+            if removed.is_none() {
+                removed = Some(std::mem::take(v));
+            }
+            false
+        });
+        removed
+    }
+
+    pub(crate) fn entries(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.entries
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries.iter().map(|(k, v)| (&k[..], &v[..]))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|(k, _)| &k[..])
+    }
+}
+
+fn bad_data(key: &[u8], expected: &str, value: &[u8]) -> Error {
+    Error::new(ErrorKind::BadData)
+        .with_value(expected, value)
+        .with_key(key)
+}
+
+impl<K, V> FromIterator<(K, V)> for AmpBox
+where
+    K: Into<Vec<u8>>,
+    V: Into<Vec<u8>>,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        AmpBox {
+            entries: iter
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+impl IntoIterator for AmpBox {
+    type Item = (Vec<u8>, Vec<u8>);
+    type IntoIter = std::vec::IntoIter<(Vec<u8>, Vec<u8>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+// Keys and values are mostly text, so they are shown as escaped strings
+// rather than lists of numbers.
+impl fmt::Debug for AmpBox {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_map()
+            .entries(self.iter().map(|(k, v)| (Escaped(k), Escaped(v))))
+            .finish()
+    }
+}
+
+struct Escaped<'a>(&'a [u8]);
+
+impl<'a> fmt::Debug for Escaped<'a> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "\"{}\"", self.0.escape_ascii())
+    }
+}
+
+impl Serialize for AmpBox {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.entries.len()))?;
+        for (key, value) in &self.entries {
+            map.serialize_entry(&Bytes(key), &Bytes(value))?;
+        }
+        map.end()
+    }
+}
+
+// Keys and values are serialized as bytes, not as sequences of numbers.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for AmpBox {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(AmpBoxVisitor)
+    }
+}
+
+struct AmpBoxVisitor;
+
+impl<'de> Visitor<'de> for AmpBoxVisitor {
+    type Value = AmpBox;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an AMP box")
+    }
+
+    fn visit_map<A>(self, mut access: A) -> std::result::Result<AmpBox, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut amp_box = AmpBox::new();
+        while let Some((ByteBuf(key), ByteBuf(value))) = access.next_entry()? {
+            amp_box.append(key, value);
+        }
+        Ok(amp_box)
+    }
+}
+
+// Keys and values are deserialized as bytes, accepting strings too, for
+// formats which have no bytes of their own.
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte string")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<ByteBuf, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<ByteBuf, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<ByteBuf, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(v.as_bytes().to_vec()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<ByteBuf, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(v.into_bytes()))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::char_lit_as_u8, clippy::unnecessary_cast)]
+mod test {
+    use super::*;
+    use crate::{from_bytes, to_amp};
+
+    #[test]
+    fn test_insert() {
+        let mut amp_box = AmpBox::new();
+        amp_box.append("a", "1");
+        amp_box.append("b", "2");
+        amp_box.append("a", "3");
+        assert_eq!(Some(b"1".to_vec()), amp_box.insert("a", "4"));
+        assert_eq!(None, amp_box.insert("c", "5"));
+        assert_eq!(
+            vec![(&b"a"[..], &b"4"[..]), (b"b", b"2"), (b"c", b"5")],
+            amp_box.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut amp_box: AmpBox = vec![("a", "1"), ("b", "2"), ("a", "3")]
+            .into_iter()
+            .collect();
+        assert_eq!(Some(b"1".to_vec()), amp_box.remove("a"));
+        assert_eq!(None, amp_box.remove("a"));
+        assert_eq!(1, amp_box.len());
+    }
+
+    #[test]
+    fn test_get_all() {
+        let amp_box: AmpBox = vec![("a", "1"), ("b", "2"), ("a", "3")]
+            .into_iter()
+            .collect();
+        assert_eq!(Some(&b"1"[..]), amp_box.get("a"));
+        assert_eq!(
+            vec![&b"1"[..], b"3"],
+            amp_box.get_all("a").collect::<Vec<_>>()
+        );
+        assert!(!amp_box.contains_key("c"));
+    }
+
+    #[test]
+    fn test_get_str() {
+        let mut amp_box = AmpBox::new();
+        amp_box.insert("a", "an string");
+        amp_box.insert("b", vec![0xff_u8]);
+        assert_eq!(Some("an string"), amp_box.get_str("a").unwrap());
+        assert_eq!(None, amp_box.get_str("c").unwrap());
+
+        let error = amp_box.get_str("b").unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some("b"), error.key());
+    }
+
+    #[test]
+    fn test_get_int() {
+        let mut amp_box = AmpBox::new();
+        amp_box.insert("a", "-83");
+        amp_box.insert("b", "x");
+        assert_eq!(Some(-83), amp_box.get_int("a").unwrap());
+        assert_eq!(None, amp_box.get_int("c").unwrap());
+
+        let error = amp_box.get_int("b").unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some("i64"), error.expected());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let amp_box: AmpBox = vec![("a", &b"1"[..]), ("a", &[0xff_u8][..])]
+            .into_iter()
+            .collect();
+        let expected = vec![
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'a' as u8,
+            0 as u8, 1 as u8, 0xff as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&amp_box).unwrap());
+    }
+
+    #[test]
+    fn test_serialize_empty_key() {
+        let amp_box: AmpBox = vec![("", "1")].into_iter().collect();
+        assert_eq!(&ErrorKind::EmptyKey, to_amp(&amp_box).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'a' as u8,
+            0 as u8, 1 as u8, 0xff as u8, 0 as u8, 0 as u8,
+        ];
+        let amp_box: AmpBox = from_bytes(&value).unwrap();
+        assert_eq!(
+            vec![&b"1"[..], &[0xff_u8]],
+            amp_box.get_all("a").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_debug() {
+        let amp_box: AmpBox = vec![("a", &b"1\n"[..]), ("b", &[0xff_u8][..])]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            "{\"a\": \"1\\n\", \"b\": \"\\xff\"}",
+            format!("{:?}", amp_box)
+        );
+    }
+}
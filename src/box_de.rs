@@ -0,0 +1,277 @@
+use std::slice;
+
+use serde::de::value::BorrowedBytesDeserializer;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, MapAccess, VariantAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use crate::amp_box::AmpBox;
+use crate::de::{from_bytes_seed, from_value_seed};
+use crate::error::{Error, Result};
+
+// Builds a value straight from an `AmpBox`, as `from_bytes` would decode the
+// box's encoding, without encoding the box as a whole.
+pub fn from_box<T>(amp_box: AmpBox) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(BoxDeserializer { amp_box: &amp_box })
+}
+
+// The box's keys are handed to visitors as bytes, and each value is decoded
+// by the regular `Deserializer`, as though it were inside of a box.
+struct BoxDeserializer<'a> {
+    amp_box: &'a AmpBox,
+}
+
+impl<'de> de::Deserializer<'de> for BoxDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_map(BoxAccess {
+            entries: self.amp_box.entries().iter(),
+            value: &[],
+            key: &[],
+        })
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // See `Serializer::start_variant` for the encoding of enums: a box with
+    // the variant name as its only key.
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.amp_box.entries() {
+            [(variant, value)] => visitor.visit_enum(BoxEnumAccess { variant, value }),
+            _ => Err(de::Error::invalid_length(
+                self.amp_box.len(),
+                &"a box with a single key",
+            )),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct BoxAccess<'de> {
+    entries: slice::Iter<'de, (Vec<u8>, Vec<u8>)>,
+
+    // The value of the key that was just read, and the key itself to report
+    // errors against.
+    value: &'de [u8],
+    key: &'de [u8],
+}
+
+impl<'de> MapAccess<'de> for BoxAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.key = key;
+                self.value = value;
+                seed.deserialize(BorrowedBytesDeserializer::<Error>::new(key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        from_value_seed(seed, self.value).map_err(|err| err.with_key(self.key))
+    }
+}
+
+struct BoxEnumAccess<'de> {
+    variant: &'de [u8],
+    value: &'de [u8],
+}
+
+impl<'de> EnumAccess<'de> for BoxEnumAccess<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(BorrowedBytesDeserializer::<Error>::new(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for BoxEnumAccess<'de> {
+    type Error = Error;
+
+    // A unit variant is a bare name, never a box.
+    fn unit_variant(self) -> Result<()> {
+        Err(de::Error::invalid_type(
+            de::Unexpected::Map,
+            &"a unit variant",
+        ))
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        from_value_seed(seed, self.value).map_err(|err| err.with_key(self.variant))
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        from_value_seed(TupleSeed { len, visitor }, self.value)
+            .map_err(|err| err.with_key(self.variant))
+    }
+
+    // A struct variant's data is a complete box of its own.
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        from_bytes_seed(MapSeed { visitor }, self.value, 0)
+            .map_err(|err| err.with_key(self.variant))
+    }
+}
+
+// Seeds to drive a visitor from inside of `from_value_seed` and
+// `from_bytes_seed`.
+struct TupleSeed<V> {
+    len: usize,
+    visitor: V,
+}
+
+impl<'de, V> DeserializeSeed<'de> for TupleSeed<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<V::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(self.len, self.visitor)
+    }
+}
+
+struct MapSeed<V> {
+    visitor: V,
+}
+
+impl<'de, V> DeserializeSeed<'de> for MapSeed<V>
+where
+    V: Visitor<'de>,
+{
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<V::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self.visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_from_box() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            value: usize,
+            name: String,
+        }
+
+        let amp_box: AmpBox = vec![("value", "83"), ("name", "Kilroy")]
+            .into_iter()
+            .collect();
+        let expected = TestStruct {
+            value: 83,
+            name: "Kilroy".to_string(),
+        };
+        assert_eq!(expected, from_box(amp_box).unwrap());
+    }
+
+    #[test]
+    fn test_from_box_map() {
+        let amp_box: AmpBox = vec![("a", "1"), ("b", "2")].into_iter().collect();
+        let actual: HashMap<String, u8> = from_box(amp_box).unwrap();
+        assert_eq!(Some(&2), actual.get("b"));
+    }
+
+    #[test]
+    fn test_from_box_bad_value() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct TestStruct {
+            value: u8,
+        }
+
+        let amp_box: AmpBox = vec![("value", "256")].into_iter().collect();
+        let error = from_box::<TestStruct>(amp_box).unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some("value"), error.key());
+        assert_eq!(Some(&b"256"[..]), error.value());
+    }
+
+    #[test]
+    fn test_from_box_enum_too_many_keys() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        enum TestEnum {
+            Newtype(u8),
+        }
+
+        let amp_box: AmpBox = vec![("Newtype", "1"), ("Newtype", "2")]
+            .into_iter()
+            .collect();
+        assert!(from_box::<TestEnum>(amp_box).is_err());
+    }
+}
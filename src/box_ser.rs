@@ -0,0 +1,394 @@
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::amp_box::AmpBox;
+use crate::error::{Error, ErrorKind, Result};
+use crate::ser::{to_key, to_value, usize_to_bytes};
+
+// Builds an `AmpBox` straight from a value, as `to_amp` would encode it,
+// without encoding the box as a whole.
+pub fn to_box<T>(value: &T) -> Result<AmpBox>
+where
+    T: ?Sized + Serialize,
+{
+    value.serialize(BoxSerializer)
+}
+
+fn not_a_box(name: &'static str) -> Error {
+    Error::new(ErrorKind::UnsupportedType(name))
+}
+
+// Only things that are boxes themselves can become an `AmpBox`: structs,
+// maps and enum variants carrying data. Their keys and values are encoded
+// by the regular `Serializer`.
+struct BoxSerializer;
+
+impl ser::Serializer for BoxSerializer {
+    type Ok = AmpBox;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<AmpBox, Error>;
+    type SerializeTuple = Impossible<AmpBox, Error>;
+    type SerializeTupleStruct = Impossible<AmpBox, Error>;
+    type SerializeTupleVariant = VariantSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer;
+
+    fn serialize_bool(self, _v: bool) -> Result<AmpBox> {
+        Err(not_a_box("bool"))
+    }
+    fn serialize_char(self, _v: char) -> Result<AmpBox> {
+        Err(not_a_box("char"))
+    }
+    fn serialize_str(self, _v: &str) -> Result<AmpBox> {
+        Err(not_a_box("str"))
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<AmpBox> {
+        Err(not_a_box("u8"))
+    }
+    fn serialize_u16(self, _v: u16) -> Result<AmpBox> {
+        Err(not_a_box("u16"))
+    }
+    fn serialize_u32(self, _v: u32) -> Result<AmpBox> {
+        Err(not_a_box("u32"))
+    }
+    fn serialize_u64(self, _v: u64) -> Result<AmpBox> {
+        Err(not_a_box("u64"))
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<AmpBox> {
+        Err(not_a_box("i8"))
+    }
+    fn serialize_i16(self, _v: i16) -> Result<AmpBox> {
+        Err(not_a_box("i16"))
+    }
+    fn serialize_i32(self, _v: i32) -> Result<AmpBox> {
+        Err(not_a_box("i32"))
+    }
+    fn serialize_i64(self, _v: i64) -> Result<AmpBox> {
+        Err(not_a_box("i64"))
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<AmpBox> {
+        Err(not_a_box("f32"))
+    }
+    fn serialize_f64(self, _v: f64) -> Result<AmpBox> {
+        Err(not_a_box("f64"))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<AmpBox> {
+        Err(not_a_box("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<AmpBox> {
+        Err(not_a_box("option"))
+    }
+    fn serialize_unit(self) -> Result<AmpBox> {
+        Err(not_a_box("unit"))
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<AmpBox>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<AmpBox> {
+        self.serialize_unit()
+    }
+
+    // On its own, `to_amp` writes a unit variant as its bare name followed by
+    // the terminator, a key with no value, which an `AmpBox` can't hold.
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<AmpBox> {
+        Err(not_a_box("unit variant"))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<AmpBox>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<AmpBox>
+    where
+        T: ?Sized + Serialize,
+    {
+        let value = match to_value(value).map_err(|err| err.with_key(variant.as_bytes()))? {
+            Some(value) => value,
+            None => return Err(Error::new(ErrorKind::UnsupportedType("option"))),
+        };
+        let mut amp_box = AmpBox::new();
+        amp_box.append(to_key(variant)?, value);
+        Ok(amp_box)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(not_a_box("seq"))
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(not_a_box("tuple"))
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(not_a_box("tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Ok(VariantSerializer::new(variant))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(MapSerializer {
+            amp_box: AmpBox::new(),
+            key: None,
+        })
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Ok(VariantSerializer::new(variant))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct MapSerializer {
+    amp_box: AmpBox,
+
+    // The key of the value about to be serialized.
+    key: Option<Vec<u8>>,
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = AmpBox;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.key = Some(to_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => {
+                return Err(Error::new(ErrorKind::Message(
+                    "value serialized before its key".to_string(),
+                )))
+            }
+        };
+        // As with `Serializer`, `None` leaves out the key altogether.
+        if let Some(value) = to_value(value).map_err(|err| err.with_key(&key))? {
+            self.amp_box.append(key, value);
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<AmpBox> {
+        Ok(self.amp_box)
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = AmpBox;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeMap::serialize_entry(self, key, value)
+    }
+
+    fn end(self) -> Result<AmpBox> {
+        Ok(self.amp_box)
+    }
+}
+
+// A tuple or struct variant becomes a box with the variant name as its only
+// key. The value is built up just as `Serializer` would: a ListOf of the
+// fields for a tuple variant, or a nested box for a struct variant.
+struct VariantSerializer {
+    variant: &'static str,
+    output: Vec<u8>,
+}
+
+impl VariantSerializer {
+    fn new(variant: &'static str) -> Self {
+        VariantSerializer {
+            variant,
+            output: vec![],
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<()> {
+        self.output.extend(usize_to_bytes(bytes.len())?);
+        self.output.extend(bytes);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<AmpBox> {
+        usize_to_bytes(self.output.len()).map_err(|err| err.with_key(self.variant.as_bytes()))?;
+        let mut amp_box = AmpBox::new();
+        amp_box.append(to_key(self.variant)?, self.output);
+        Ok(amp_box)
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer {
+    type Ok = AmpBox;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match to_value(value)? {
+            Some(value) => self.push(&value),
+            None => Err(Error::new(ErrorKind::UnsupportedType("option"))),
+        }
+    }
+
+    fn end(self) -> Result<AmpBox> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer {
+    type Ok = AmpBox;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if let Some(value) = to_value(value).map_err(|err| err.with_key(key.as_bytes()))? {
+            self.push(&to_key(key)?)?;
+            self.push(&value)?;
+        }
+        Ok(())
+    }
+
+    fn end(mut self) -> Result<AmpBox> {
+        self.output.extend([0_u8, 0_u8]);
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+
+    use super::*;
+
+    #[test]
+    fn test_to_box() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            value: usize,
+            name: &'static str,
+        }
+
+        let value = TestStruct {
+            value: 83,
+            name: "Kilroy",
+        };
+        let expected: AmpBox = vec![("value", "83"), ("name", "Kilroy")]
+            .into_iter()
+            .collect();
+        assert_eq!(expected, to_box(&value).unwrap());
+    }
+
+    #[test]
+    fn test_to_box_not_a_box() {
+        assert_eq!(
+            &ErrorKind::UnsupportedType("u8"),
+            to_box(&83_u8).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_to_box_unit_variant() {
+        #[derive(Serialize)]
+        enum TestEnum {
+            Unit,
+        }
+
+        assert_eq!(
+            b"\x00\x04Unit\x00\x00",
+            &crate::to_amp(&TestEnum::Unit).unwrap()[..]
+        );
+        assert_eq!(
+            &ErrorKind::UnsupportedType("unit variant"),
+            to_box(&TestEnum::Unit).unwrap_err().kind()
+        );
+        assert!(crate::to_writer(vec![], &TestEnum::Unit).is_err());
+    }
+
+    #[test]
+    fn test_to_box_nested_struct() {
+        #[derive(Serialize)]
+        struct NestedStruct {
+            inner: usize,
+        }
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            nested: NestedStruct,
+        }
+
+        let value = TestStruct {
+            nested: NestedStruct { inner: 1 },
+        };
+        let amp_box = to_box(&value).unwrap();
+        assert_eq!(
+            Some(&b"\x00\x05inner\x00\x011\x00\x00"[..]),
+            amp_box.get("nested")
+        );
+    }
+
+    #[test]
+    fn test_to_box_empty_key() {
+        let value: std::collections::BTreeMap<&str, &str> = vec![("", "1")].into_iter().collect();
+        assert_eq!(&ErrorKind::EmptyKey, to_box(&value).unwrap_err().kind());
+    }
+}
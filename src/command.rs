@@ -0,0 +1,168 @@
+use std::convert::Infallible;
+use std::fmt::Display;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::de::from_bytes;
+use crate::error::Result;
+use crate::ser::to_amp;
+
+// An AMP command, as declared in Twisted by subclassing `amp.Command`:
+//
+//     struct Sum;
+//
+//     impl Command for Sum {
+//         const NAME: &'static str = "Sum";
+//         type Arguments = SumArguments;
+//         type Response = SumResponse;
+//         type Error = Infallible;
+//     }
+//
+// Unlike Twisted, the name isn't taken from the type, so it has to match the
+// name the other side uses.
+pub trait Command {
+    // Sent as `_command`.
+    const NAME: &'static str;
+
+    // Whether the sender waits for a response. When it doesn't, the command
+    // is sent without an `_ask` tag, and neither a response nor an error is
+    // sent back.
+    const REQUIRES_ANSWER: bool = true;
+
+    type Arguments: Serialize + DeserializeOwned;
+    type Response: Serialize + DeserializeOwned;
+
+    // The errors the command declares, which are sent back with their
+    // error code. `Infallible` declares none.
+    type Error: CommandError;
+
+    // These are Twisted's `makeArguments`, `parseArguments`, `makeResponse`
+    // and `parseResponse`, encoding and decoding the bare box.
+    fn make_arguments(arguments: &Self::Arguments) -> Result<Vec<u8>> {
+        to_amp(arguments)
+    }
+
+    fn parse_arguments(bytes: &[u8]) -> Result<Self::Arguments> {
+        from_bytes(bytes)
+    }
+
+    fn make_response(response: &Self::Response) -> Result<Vec<u8>> {
+        to_amp(response)
+    }
+
+    fn parse_response(bytes: &[u8]) -> Result<Self::Response> {
+        from_bytes(bytes)
+    }
+}
+
+// An error declared by a command, as in the `errors` of Twisted's
+// `amp.Command`. It goes over the wire as its code, along with its `Display`
+// as a description.
+pub trait CommandError: Display + Sized {
+    // Sent as `_error_code`.
+    fn code(&self) -> &str;
+
+    // The declared error with the given code, or `None` when the code isn't
+    // one of the command's.
+    fn from_code(code: &str, description: &str) -> Option<Self>;
+
+    // Whether the connection should be closed once the error is sent, as for
+    // Twisted's `fatalErrors`.
+    fn is_fatal(&self) -> bool {
+        false
+    }
+}
+
+impl CommandError for Infallible {
+    fn code(&self) -> &str {
+        match *self {}
+    }
+
+    fn from_code(_code: &str, _description: &str) -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::amp_box::AmpBox;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct SumArguments {
+        a: i64,
+        b: i64,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct SumResponse {
+        total: i64,
+    }
+
+    struct Sum;
+
+    impl Command for Sum {
+        const NAME: &'static str = "Sum";
+        type Arguments = SumArguments;
+        type Response = SumResponse;
+        type Error = Infallible;
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum DivideError {
+        ZeroDivision,
+    }
+
+    impl fmt::Display for DivideError {
+        fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("division by zero")
+        }
+    }
+
+    impl CommandError for DivideError {
+        fn code(&self) -> &str {
+            "ZERO_DIVISION"
+        }
+
+        fn from_code(code: &str, _description: &str) -> Option<Self> {
+            match code {
+                "ZERO_DIVISION" => Some(DivideError::ZeroDivision),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_command_arguments() {
+        let arguments = SumArguments { a: 13, b: 81 };
+        let bytes = Sum::make_arguments(&arguments).unwrap();
+        let amp_box: AmpBox = from_bytes(&bytes).unwrap();
+        assert_eq!(Some(13), amp_box.get_int("a").unwrap());
+        assert_eq!(arguments, Sum::parse_arguments(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_command_response() {
+        let response = SumResponse { total: 94 };
+        let bytes = Sum::make_response(&response).unwrap();
+        assert_eq!(response, Sum::parse_response(&bytes).unwrap());
+        assert!(Sum::parse_arguments(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_command_error() {
+        let error = DivideError::ZeroDivision;
+        assert_eq!(
+            Some(DivideError::ZeroDivision),
+            DivideError::from_code(error.code(), &error.to_string())
+        );
+        assert_eq!(None, DivideError::from_code("UNKNOWN", ""));
+        assert!(!error.is_fatal());
+        assert!(Infallible::from_code("ZERO_DIVISION", "").is_none());
+    }
+}
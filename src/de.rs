@@ -1,22 +1,61 @@
-use std::str;
+use std::any;
+use std::io;
+use std::marker::PhantomData;
+use std::str::{self, FromStr};
 
 use byteorder::{BigEndian, ByteOrder};
 use serde::de;
-use serde::de::{DeserializeSeed, MapAccess, Visitor};
+use serde::de::DeserializeOwned;
+use serde::de::{DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
 use serde::Deserialize;
 
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 
 struct Deserializer<'de> {
     index: usize,
     input: &'de [u8],
+
+    // Where `input` starts within the whole box, so that errors in nested
+    // values can report where they happened.
+    offset: usize,
+
+    // How deeply nested the value being deserialized is. At depth zero, we
+    // are reading the box itself; anything deeper is a value inside of a box.
+    depth: usize,
+
+    // The depth of the elements of the sequence being deserialized, if any.
+    // Structs and maps as elements are complete boxes, each with its own
+    // terminator, like Twisted's AmpList.
+    element_depth: Option<usize>,
+
+    // Whether the elements read so far have been boxes. Elements which are
+    // skipped, such as those past the end of a tuple, are then skipped as
+    // boxes too, as they carry no length prefix of their own.
+    element_boxes: bool,
+
+    // How many more levels of nesting we are willing to recurse into, so a
+    // maliciously nested box can't overflow the stack.
+    remaining_depth: usize,
+
+    // Whether `input` is a single value without its length prefix, as held
+    // by an `AmpBox`.
+    unprefixed: bool,
 }
 
+// Comfortably more than any real world box, while keeping recursion shallow.
+const RECURSION_LIMIT: usize = 128;
+
 impl<'de> Deserializer<'de> {
     pub fn from_bytes(bytes: &'de [u8]) -> Self {
         Self {
             index: 0,
             input: bytes,
+            offset: 0,
+            depth: 0,
+            element_depth: None,
+            element_boxes: false,
+            remaining_depth: RECURSION_LIMIT,
+            unprefixed: false,
         }
     }
 }
@@ -25,74 +64,455 @@ pub fn from_bytes<'a, T>(bytes: &'a [u8]) -> Result<T>
 where
     T: Deserialize<'a>,
 {
-    let mut deserializer = Deserializer::from_bytes(bytes);
-    let t = T::deserialize(&mut deserializer).unwrap();
-    if deserializer.done() {
+    from_bytes_at(bytes, 0)
+}
+
+// Deserialize a box found at `offset` within a larger stream of bytes.
+fn from_bytes_at<'a, T>(bytes: &'a [u8], offset: usize) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    from_bytes_seed(PhantomData, bytes, offset)
+}
+
+pub(crate) fn from_bytes_seed<'a, T>(seed: T, bytes: &'a [u8], offset: usize) -> Result<T::Value>
+where
+    T: DeserializeSeed<'a>,
+{
+    let mut deserializer = Deserializer {
+        offset,
+        ..Deserializer::from_bytes(bytes)
+    };
+    let t = seed
+        .deserialize(&mut deserializer)
+        .map_err(|err| err.with_offset(offset + deserializer.index))?;
+    if deserializer.done()? {
         Ok(t)
     } else {
-        Err(Error::TrailingCharacters)
+        Err(deserializer.error(ErrorKind::TrailingCharacters))
+    }
+}
+
+// Deserialize one of an `AmpBox`'s values, as if it were inside of a box.
+pub(crate) fn from_value_seed<'a, T>(seed: T, value: &'a [u8]) -> Result<T::Value>
+where
+    T: DeserializeSeed<'a>,
+{
+    let mut deserializer = Deserializer {
+        depth: 1,
+        unprefixed: true,
+        ..Deserializer::from_bytes(value)
+    };
+    let t = seed
+        .deserialize(&mut deserializer)
+        .map_err(|err| err.with_offset(deserializer.index))?;
+    if deserializer.index == value.len() {
+        Ok(t)
+    } else {
+        Err(deserializer.error(ErrorKind::TrailingCharacters))
+    }
+}
+
+// Reads exactly one box from a reader, up to and including its 0x00 0x00
+// terminator, so that any following boxes are left unread.
+pub(crate) fn read_box<R>(reader: &mut R) -> Result<Vec<u8>>
+where
+    R: io::Read,
+{
+    match read_next_box(reader, 0)? {
+        Some(output) => Ok(output),
+        None => Err(Error::new(ErrorKind::Eof).with_offset(0)),
+    }
+}
+
+// Like `read_box`, except that a reader which ends before the box even
+// starts is a clean end of the stream, rather than a truncated box. Errors
+// are reported relative to `offset`, where the box starts in the stream.
+pub(crate) fn read_next_box<R>(reader: &mut R, offset: usize) -> Result<Option<Vec<u8>>>
+where
+    R: io::Read,
+{
+    let mut first = [0_u8; 1];
+    loop {
+        match reader.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Error::from(err).with_offset(offset)),
+        }
+    }
+
+    let mut rest = [0_u8; 1];
+    reader
+        .read_exact(&mut rest)
+        .map_err(|err| Error::from(err).with_offset(offset))?;
+    let mut output = vec![first[0], rest[0]];
+    let mut key_length = BigEndian::read_u16(&output) as usize;
+    while key_length != 0 {
+        if key_length > u8::MAX as usize {
+            let offset = offset + output.len() - 2;
+            return Err(Error::new(ErrorKind::KeyTooLong).with_offset(offset));
+        }
+        read_box_bytes(reader, &mut output, key_length, offset)?;
+        let value_length = read_box_length(reader, &mut output, offset)?;
+        read_box_bytes(reader, &mut output, value_length, offset)?;
+        key_length = read_box_length(reader, &mut output, offset)?;
+    }
+    Ok(Some(output))
+}
+
+fn read_box_length<R>(reader: &mut R, output: &mut Vec<u8>, offset: usize) -> Result<usize>
+where
+    R: io::Read,
+{
+    let mut bytes = [0_u8; 2];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|err| Error::from(err).with_offset(offset + output.len()))?;
+    output.extend(bytes);
+    Ok(BigEndian::read_u16(&bytes) as usize)
+}
+
+fn read_box_bytes<R>(
+    reader: &mut R,
+    output: &mut Vec<u8>,
+    count: usize,
+    offset: usize,
+) -> Result<()>
+where
+    R: io::Read,
+{
+    let start = output.len();
+    output.resize(start + count, 0);
+    reader
+        .read_exact(&mut output[start..])
+        .map_err(|err| Error::from(err).with_offset(offset + start))
+}
+
+// Only whole boxes can be read from a stream, as the terminator is the only
+// way to tell where a box ends. The box is read a key or value at a time,
+// so that nothing past its terminator is consumed, which makes for many
+// small reads; wrap an unbuffered reader, such as a `TcpStream`, in a
+// `std::io::BufReader`.
+pub fn from_reader<R, T>(mut reader: R) -> Result<T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    let bytes = read_box(&mut reader)?;
+    from_bytes(&bytes)
+}
+
+// A source of boxes for a `StreamDeserializer` reading from `std::io`. As
+// with `from_reader`, boxes are read a key or value at a time, so the reader
+// is best wrapped in a `std::io::BufReader`.
+pub struct IoRead<R> {
+    reader: R,
+}
+
+// Iterates over each box in a stream of consecutive boxes, as on an AMP
+// connection. A stream ending between boxes is a clean end, while one ending
+// part way through a box yields an `ErrorKind::Eof` error. A box that fails
+// to decode yields its error, and iteration carries on with the next box;
+// after a malformed or truncated box, there's no telling where the next one
+// starts, so iteration stops.
+pub struct StreamDeserializer<R, T> {
+    read: R,
+    offset: usize,
+    failed: bool,
+    output: PhantomData<T>,
+}
+
+impl<'de, T> StreamDeserializer<&'de [u8], T>
+where
+    T: Deserialize<'de>,
+{
+    pub fn from_bytes(bytes: &'de [u8]) -> Self {
+        StreamDeserializer {
+            read: bytes,
+            offset: 0,
+            failed: false,
+            output: PhantomData,
+        }
+    }
+}
+
+impl<R, T> StreamDeserializer<IoRead<R>, T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    pub fn from_reader(reader: R) -> Self {
+        StreamDeserializer {
+            read: IoRead { reader },
+            offset: 0,
+            failed: false,
+            output: PhantomData,
+        }
+    }
+}
+
+impl<R, T> StreamDeserializer<R, T> {
+    // The number of bytes consumed so far, i.e. where the next box starts.
+    pub fn byte_offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'de, T> Iterator for StreamDeserializer<&'de [u8], T>
+where
+    T: Deserialize<'de>,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed || self.offset == self.read.len() {
+            return None;
+        }
+
+        let input = &self.read[self.offset..];
+        let mut deserializer = Deserializer {
+            offset: self.offset,
+            ..Deserializer::from_bytes(input)
+        };
+        if let Err(err) = deserializer.skip_box() {
+            self.failed = true;
+            return Some(Err(err));
+        }
+
+        let offset = self.offset;
+        self.offset += deserializer.index;
+        Some(from_bytes_at(&input[..deserializer.index], offset))
+    }
+}
+
+impl<R, T> Iterator for StreamDeserializer<IoRead<R>, T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed {
+            return None;
+        }
+
+        match read_next_box(&mut self.read.reader, self.offset) {
+            Ok(Some(bytes)) => {
+                let offset = self.offset;
+                self.offset += bytes.len();
+                Some(from_bytes_at(&bytes, offset))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
     }
 }
 
 impl<'de> Deserializer<'de> {
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error::new(kind).with_offset(self.offset + self.index)
+    }
     fn peek_length(&self) -> Result<u16> {
-        let mut bytes: [u8; 2] = [0, 0];
-        bytes[0] = self.input[self.index];
-        bytes[1] = self.input[self.index + 1];
-
-        let length = BigEndian::read_u16(&bytes);
-        Ok(length)
+        match self.input.get(self.index..self.index + 2) {
+            Some(bytes) => Ok(BigEndian::read_u16(bytes)),
+            None => Err(self.error(ErrorKind::Eof)),
+        }
     }
     fn read_length(&mut self) -> Result<u16> {
+        if self.unprefixed {
+            self.unprefixed = false;
+            return u16::try_from(self.input.len())
+                .map_err(|_| self.error(ErrorKind::ValueTooLong));
+        }
         let length = self.peek_length();
         self.index += 2;
         length
     }
     fn read_str(&mut self, count: u16) -> Result<&'de str> {
         let new_value = self.index + count as usize;
-        match str::from_utf8(&self.input[self.index..new_value]) {
+        let bytes = match self.input.get(self.index..new_value) {
+            Some(bytes) => bytes,
+            None => return Err(self.error(ErrorKind::Eof)),
+        };
+        match str::from_utf8(bytes) {
             Ok(string) => {
                 self.index = new_value;
                 Ok(string)
             }
-            Err(_) => Err(Error::BadData),
+            Err(_) => Err(self
+                .error(ErrorKind::BadData)
+                .with_value("a UTF-8 string", bytes)),
         }
     }
     fn read_next_value(&mut self) -> Result<String> {
-        let length = self.read_length().unwrap();
-        let value = self.read_str(length).unwrap();
+        let length = self.read_length()?;
+        let value = self.read_str(length)?;
         Ok(String::from(value))
     }
     fn read_next_value_as_str(&mut self) -> Result<&'de str> {
-        let length = self.read_length().unwrap();
-        let value = self.read_str(length).unwrap();
+        let length = self.read_length()?;
+        let value = self.read_str(length)?;
         Ok(value)
     }
-    fn done(&self) -> bool {
-        let length = self.peek_length().unwrap();
-        length == 0
+    fn read_next_value_as_bytes(&mut self) -> Result<&'de [u8]> {
+        let length = self.read_length()?;
+        let new_value = self.index + length as usize;
+        match self.input.get(self.index..new_value) {
+            Some(bytes) => {
+                self.index = new_value;
+                Ok(bytes)
+            }
+            None => Err(self.error(ErrorKind::Eof)),
+        }
+    }
+    fn parse_next_value<T>(&mut self) -> Result<T>
+    where
+        T: FromStr,
+    {
+        let value = self.read_next_value_as_str()?;
+        value.parse::<T>().map_err(|_| {
+            let index = self.offset + self.index - value.len();
+            Error::new(ErrorKind::BadData)
+                .with_offset(index)
+                .with_value(any::type_name::<T>(), value.as_bytes())
+        })
+    }
+    // Keys are at most 255 bytes long, so a length whose first byte isn't
+    // 0x00 means the box is malformed.
+    fn check_key_length(&self) -> Result<()> {
+        if self.peek_length()? > u8::MAX as u16 {
+            Err(self.error(ErrorKind::KeyTooLong))
+        } else {
+            Ok(())
+        }
+    }
+    // Step over a whole box, without decoding it, to find where it ends.
+    fn skip_box(&mut self) -> Result<()> {
+        while !self.done()? {
+            self.check_key_length()?;
+            self.read_next_value_as_bytes()?;
+            self.read_next_value_as_bytes()?;
+        }
+        self.index += 2;
+        Ok(())
+    }
+    fn done(&self) -> Result<bool> {
+        let length = self.peek_length()?;
+        Ok(length == 0)
+    }
+    // Values holding their own encoded values, such as sequences and nested
+    // boxes, are read by a separate deserializer limited to that value.
+    // The nested value must be the one that was just read.
+    fn nested_deserializer(&self, input: &'de [u8], depth: usize) -> Result<Deserializer<'de>> {
+        if self.remaining_depth == 0 {
+            return Err(self.error(ErrorKind::RecursionLimitExceeded));
+        }
+        Ok(Deserializer {
+            index: 0,
+            input,
+            offset: self.offset + self.index - input.len(),
+            depth,
+            element_depth: None,
+            element_boxes: false,
+            remaining_depth: self.remaining_depth - 1,
+            unprefixed: false,
+        })
+    }
+    // A nested box carries its own 0x00 0x00 terminator, which must be the
+    // last thing in the value holding it.
+    fn end_box(&mut self) -> Result<()> {
+        if self.peek_length()? != 0 {
+            return Err(self.error(ErrorKind::BadData));
+        }
+        self.index += 2;
+        if self.index == self.input.len() {
+            Ok(())
+        } else {
+            Err(self.error(ErrorKind::TrailingCharacters))
+        }
+    }
+    // Visit a nested box, held by the value that was just read.
+    fn visit_box<V>(&self, value: &'de [u8], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let mut de = self.nested_deserializer(value, 0)?;
+        visitor
+            .visit_map(AmpAccess::new(&mut de))
+            .and_then(|result| de.end_box().map(|_| result))
+            .map_err(|err| err.with_offset(de.offset + de.index))
+    }
+    fn deserialize_nested<T>(&mut self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.remaining_depth == 0 {
+            return Err(self.error(ErrorKind::RecursionLimitExceeded));
+        }
+        self.depth += 1;
+        self.remaining_depth -= 1;
+        let result = seed.deserialize(&mut *self);
+        self.remaining_depth += 1;
+        self.depth -= 1;
+        result.map_err(|err| err.with_offset(self.offset + self.index))
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+// Whether a value is exactly one well-formed box. Keys are never empty, so a
+// box always starts with 0x00, which a string hardly ever does.
+fn holds_box(value: &[u8]) -> bool {
+    if value.first() != Some(&0) {
+        return false;
+    }
+    let mut de = Deserializer::from_bytes(value);
+    de.skip_box().is_ok() && de.index == value.len()
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
+    // At the top level, the only thing to describe is the box itself.
+    // Values inside of a box are all byte strings on the wire, which are
+    // described as a map when they hold a whole nested box, such as the
+    // content of an adjacently tagged struct variant, and otherwise as
+    // strings when they are valid UTF-8.
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_i8(1_i8)
+        if self.depth == 0 {
+            return visitor.visit_map(AmpAccess::new(self));
+        }
+
+        let value = self.read_next_value_as_bytes()?;
+        if holds_box(value) {
+            return self.visit_box(value, visitor);
+        }
+        match str::from_utf8(value) {
+            Ok(string) => visitor.visit_borrowed_str(string),
+            Err(_) => visitor.visit_borrowed_bytes(value),
+        }
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        match value.as_ref() {
+        let value = self.read_next_value_as_str()?;
+        match value {
             "True" => visitor.visit_bool(true),
             "False" => visitor.visit_bool(false),
-            _ => Err(Error::BadData),
+            _ => {
+                let index = self.offset + self.index - value.len();
+                Err(Error::new(ErrorKind::BadData)
+                    .with_offset(index)
+                    .with_value("bool", value.as_bytes()))
+            }
         }
     }
 
@@ -100,90 +520,79 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_i8(value.parse::<i8>().unwrap())
+        visitor.visit_i8(self.parse_next_value()?)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_i16(value.parse::<i16>().unwrap())
+        visitor.visit_i16(self.parse_next_value()?)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_i32(value.parse::<i32>().unwrap())
+        visitor.visit_i32(self.parse_next_value()?)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_i64(value.parse::<i64>().unwrap())
+        visitor.visit_i64(self.parse_next_value()?)
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_u8(value.parse::<u8>().unwrap())
+        visitor.visit_u8(self.parse_next_value()?)
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_u16(value.parse::<u16>().unwrap())
+        visitor.visit_u16(self.parse_next_value()?)
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_u32(value.parse::<u32>().unwrap())
+        visitor.visit_u32(self.parse_next_value()?)
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_u64(value.parse::<u64>().unwrap())
+        visitor.visit_u64(self.parse_next_value()?)
     }
 
-    // Float parsing is stupidly hard.
+    // Rust parses everything Python's `repr` writes for `amp.Float`,
+    // including `nan`, `inf`, `-inf` and exponents like `1e+100`.
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_f32(value.parse::<f32>().unwrap())
+        visitor.visit_f32(self.parse_next_value()?)
     }
 
-    // Float parsing is stupidly hard.
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_f64(value.parse::<f64>().unwrap())
+        visitor.visit_f64(self.parse_next_value()?)
     }
 
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
-        visitor.visit_char(value.parse::<char>().unwrap())
+        visitor.visit_char(self.parse_next_value()?)
     }
 
     // Refer to the "Understanding deserializer lifetimes" page for information
@@ -192,7 +601,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value_as_str().unwrap();
+        let value = self.read_next_value_as_str()?;
         visitor.visit_borrowed_str(value)
     }
 
@@ -200,38 +609,41 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        let value = self.read_next_value().unwrap();
+        let value = self.read_next_value()?;
         visitor.visit_string(value)
     }
 
-    // The `Serializer` implementation on the previous page serialized byte
-    // arrays as JSON arrays of bytes. Handle that representation here.
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value>
+    // AMP values are byte strings, so bytes are borrowed straight from the
+    // input, whether or not they are valid UTF-8.
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        let value = self.read_next_value_as_bytes()?;
+        visitor.visit_borrowed_bytes(value)
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_bytes(visitor)
     }
 
-    fn deserialize_option<V>(self, _visitor: V) -> Result<V::Value>
+    // A missing optional value is a missing key, which serde handles by
+    // itself, so any value that is there at all is `Some`.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_some(self)
     }
 
     fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        Err(self.error(ErrorKind::UnsupportedType("unit")))
     }
 
     // Unit struct means a named value containing no data.
@@ -252,39 +664,76 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_seq<V>(self, _visitor: V) -> Result<V::Value>
+    // Sequences are encoded as Twisted's ListOf: a single value holding each
+    // element as its own length-prefixed value.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        let value = self.read_next_value_as_bytes()?;
+        let mut access = AmpListAccess::new(self.nested_deserializer(value, 1)?);
+        let result = visitor.visit_seq(&mut access)?;
+        access.end()?;
+        Ok(result)
     }
 
-    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    // Tuples are encoded just like sequences. serde's visitors already
+    // complain about too few elements, but stop reading once they have all
+    // they need, so too many elements must be caught here.
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        let value = self.read_next_value_as_bytes()?;
+        let mut access = AmpListAccess::new(self.nested_deserializer(value, 1)?);
+        let result = visitor.visit_seq(&mut access)?;
+        if !access.is_done() {
+            let mut count = len;
+            while access.next_element::<de::IgnoredAny>()?.is_some() {
+                count += 1;
+            }
+            let expected = format!("a tuple of size {}", len);
+            let err: Error = de::Error::invalid_length(count, &expected.as_str());
+            return Err(err.with_offset(access.de.offset + access.de.index));
+        }
+        Ok(result)
     }
 
     fn deserialize_tuple_struct<V>(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        self.deserialize_tuple(len, visitor)
     }
 
-    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
+    // See `Serializer::start_map` for the encoding of structs and maps. At the
+    // top level, they are the box itself, while anywhere else they are a
+    // nested box, either length-prefixed or as an element of a sequence.
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!();
-        //let value = visitor.visit_map(AmpAccess::new(&mut self)).unwrap();
-        //Ok(value)
+        if self.depth == 0 {
+            return visitor.visit_map(AmpAccess::new(self));
+        }
+
+        if self.element_depth == Some(self.depth) {
+            self.element_boxes = true;
+            let result = visitor.visit_map(AmpAccess::new(self))?;
+            if !self.done()? {
+                return Err(self.error(ErrorKind::TrailingCharacters));
+            }
+            self.index += 2;
+            return Ok(result);
+        }
+
+        let value = self.read_next_value_as_bytes()?;
+        self.visit_box(value, visitor)
     }
 
     fn deserialize_struct<V>(
@@ -296,21 +745,42 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        //self.deserialize_map(visitor)
-        let value = visitor.visit_map(AmpAccess::new(self)).unwrap();
-        Ok(value)
+        self.deserialize_map(visitor)
     }
 
+    // See `Serializer::start_variant` for the encoding of enums. Inside of a
+    // box, a unit variant is a bare name, while any other variant is a nested
+    // box. Keys are never empty, so a nested box always starts with 0x00.
     fn deserialize_enum<V>(
         self,
         _name: &'static str,
         _variants: &'static [&'static str],
-        _visitor: V,
+        visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        if self.depth == 0 {
+            return visitor.visit_enum(AmpEnumAccess::new(self));
+        }
+
+        let value = self.read_next_value_as_bytes()?;
+        if value.first() == Some(&0) {
+            let mut de = self.nested_deserializer(value, 0)?;
+            let result = visitor.visit_enum(AmpEnumAccess::new(&mut de))?;
+            de.end_box()?;
+            Ok(result)
+        } else {
+            match str::from_utf8(value) {
+                Ok(variant) => visitor.visit_enum(de::value::BorrowedStrDeserializer::new(variant)),
+                Err(_) => {
+                    let index = self.offset + self.index - value.len();
+                    Err(Error::new(ErrorKind::BadData)
+                        .with_offset(index)
+                        .with_value("a UTF-8 string", value))
+                }
+            }
+        }
     }
 
     fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
@@ -320,21 +790,35 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         self.deserialize_str(visitor)
     }
 
+    // Values of unknown keys are skipped over without looking at them, so
+    // that they needn't be UTF-8, or anything else in particular.
     fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_any(visitor)
+        if self.depth == 0 {
+            return self.deserialize_any(visitor);
+        }
+
+        if self.element_depth == Some(self.depth) && self.element_boxes {
+            self.skip_box()?;
+        } else {
+            self.read_next_value_as_bytes()?;
+        }
+        visitor.visit_unit()
     }
 }
 
 struct AmpAccess<'a, 'de: 'a> {
     de: &'a mut Deserializer<'de>,
+
+    // The key of the value about to be read, to report errors against.
+    key: &'de [u8],
 }
 
 impl<'a, 'de> AmpAccess<'a, 'de> {
     fn new(de: &'a mut Deserializer<'de>) -> Self {
-        AmpAccess { de }
+        AmpAccess { de, key: &[] }
     }
 }
 
@@ -345,22 +829,142 @@ impl<'a, 'de> MapAccess<'de> for AmpAccess<'a, 'de> {
     where
         K: DeserializeSeed<'de>,
     {
-        if self.de.done() {
+        if self.de.done()? {
             return Ok(None);
         }
-        seed.deserialize(&mut *self.de).map(Some)
+        self.de.check_key_length()?;
+        let start = self.de.index + 2;
+        let key = self.de.deserialize_nested(seed)?;
+        self.key = self.de.input.get(start..self.de.index).unwrap_or_default();
+        Ok(Some(key))
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
     where
         V: DeserializeSeed<'de>,
     {
-        seed.deserialize(&mut *self.de)
+        self.de
+            .deserialize_nested(seed)
+            .map_err(|err| err.with_key(self.key))
+    }
+}
+
+// Elements of a ListOf-style sequence, each a length-prefixed value, read
+// until the sequence's own value is exhausted.
+struct AmpListAccess<'de> {
+    de: Deserializer<'de>,
+    count: usize,
+}
+
+impl<'de> AmpListAccess<'de> {
+    fn new(mut de: Deserializer<'de>) -> Self {
+        de.element_depth = Some(de.depth);
+        AmpListAccess { de, count: 0 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.de.index == self.de.input.len()
+    }
+
+    fn end(&self) -> Result<()> {
+        if self.is_done() {
+            Ok(())
+        } else {
+            Err(self.de.error(ErrorKind::TrailingCharacters))
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for AmpListAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.is_done() {
+            return Ok(None);
+        }
+        let index = self.count;
+        self.count += 1;
+        match seed.deserialize(&mut self.de) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) => Err(err
+                .with_offset(self.de.offset + self.de.index)
+                .with_index(index)),
+        }
+    }
+}
+
+struct AmpEnumAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+
+    // The variant's name, which is the key its data is held under.
+    variant: &'de [u8],
+}
+
+impl<'a, 'de> AmpEnumAccess<'a, 'de> {
+    fn new(de: &'a mut Deserializer<'de>) -> Self {
+        AmpEnumAccess { de, variant: &[] }
+    }
+}
+
+impl<'a, 'de> EnumAccess<'de> for AmpEnumAccess<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(mut self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.de.check_key_length()?;
+        let start = self.de.index + 2;
+        let variant = self.de.deserialize_nested(seed)?;
+        self.variant = self.de.input.get(start..self.de.index).unwrap_or_default();
+        Ok((variant, self))
+    }
+}
+
+impl<'a, 'de> VariantAccess<'de> for AmpEnumAccess<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.de
+            .deserialize_nested(seed)
+            .map_err(|err| err.with_key(self.variant))
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_tuple(&mut *self.de, len, visitor)
+            .map_err(|err| err.with_key(self.variant))
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = self.de.read_next_value_as_bytes()?;
+        self.de
+            .visit_box(value, visitor)
+            .map_err(|err| err.with_key(self.variant))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::char_lit_as_u8, clippy::unnecessary_cast)]
 mod test {
+    use std::collections::HashMap;
+
     use super::*;
 
     #[test]
@@ -537,4 +1141,680 @@ mod test {
         assert_eq!(383, actual.value);
         assert_eq!("an-name".to_string(), actual.name);
     }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum TestEnum {
+        Unit,
+        Newtype(u8),
+    }
+
+    #[test]
+    fn test_deserialize_unit_variant() {
+        let value = [
+            0 as u8, 4 as u8, 'U' as u8, 'n' as u8, 'i' as u8, 't' as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: TestEnum = from_bytes(&value).unwrap();
+        assert_eq!(TestEnum::Unit, actual);
+    }
+
+    #[test]
+    fn test_deserialize_newtype_variant() {
+        let value = [
+            0 as u8, 7 as u8, 'N' as u8, 'e' as u8, 'w' as u8, 't' as u8, 'y' as u8, 'p' as u8,
+            'e' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: TestEnum = from_bytes(&value).unwrap();
+        assert_eq!(TestEnum::Newtype(1), actual);
+    }
+
+    #[test]
+    fn test_deserialize_nested_variant() {
+        #[derive(Deserialize)]
+        struct TestStruct {
+            value: TestEnum,
+        }
+
+        let value = [
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            14 as u8, 0 as u8, 7 as u8, 'N' as u8, 'e' as u8, 'w' as u8, 't' as u8, 'y' as u8,
+            'p' as u8, 'e' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: TestStruct = from_bytes(&value).unwrap();
+        assert_eq!(TestEnum::Newtype(1), actual.value);
+    }
+
+    #[test]
+    fn test_deserialize_nested_variant_trailing_characters() {
+        #[derive(Debug, Deserialize)]
+        struct TestStruct {
+            #[allow(dead_code)]
+            value: TestEnum,
+        }
+
+        let value = [
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            15 as u8, 0 as u8, 7 as u8, 'N' as u8, 'e' as u8, 'w' as u8, 't' as u8, 'y' as u8,
+            'p' as u8, 'e' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 'x' as u8,
+            0 as u8, 0 as u8,
+        ];
+        let mut deserializer = Deserializer::from_bytes(&value);
+        let actual = TestStruct::deserialize(&mut deserializer);
+        assert_eq!(&ErrorKind::TrailingCharacters, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_map() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'b' as u8,
+            0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let actual: HashMap<String, u8> = from_bytes(&value).unwrap();
+        assert_eq!(2, actual.len());
+        assert_eq!(Some(&1), actual.get("a"));
+        assert_eq!(Some(&2), actual.get("b"));
+    }
+
+    #[test]
+    fn test_deserialize_nested_map() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            extra: HashMap<String, String>,
+            value: u8,
+        }
+
+        let value = [
+            0 as u8, 5 as u8, 'e' as u8, 'x' as u8, 't' as u8, 'r' as u8, 'a' as u8, 0 as u8,
+            8 as u8, 0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 0 as u8,
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            1 as u8, '1' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let mut extra = HashMap::new();
+        extra.insert("a".to_string(), "x".to_string());
+        let expected = TestStruct { extra, value: 1 };
+        assert_eq!(expected, from_bytes(&value).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_nested_map_trailing_characters() {
+        let value = [
+            0 as u8, 5 as u8, 'e' as u8, 'x' as u8, 't' as u8, 'r' as u8, 'a' as u8, 0 as u8,
+            9 as u8, 0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 0 as u8,
+            'y' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let actual: Result<HashMap<String, HashMap<String, String>>> = from_bytes(&value);
+        let error = actual.unwrap_err();
+        assert_eq!(&ErrorKind::TrailingCharacters, error.kind());
+        assert_eq!(Some("extra"), error.key());
+    }
+
+    #[test]
+    fn test_deserialize_deny_unknown_fields() {
+        #[derive(Debug, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct TestStruct {
+            #[allow(dead_code)]
+            a: u8,
+        }
+
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'b' as u8,
+            0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let mut deserializer = Deserializer::from_bytes(&value);
+        let actual = TestStruct::deserialize(&mut deserializer);
+        assert_eq!(
+            &ErrorKind::Message("unknown field `b`, expected `a`".to_string()),
+            actual.unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_seq() {
+        let value = [
+            0 as u8, 8 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 2 as u8, '1' as u8,
+            '1' as u8, 0 as u8, 0 as u8,
+        ];
+        let expected = vec![10, 11];
+        let actual: Vec<u8> = from_bytes(&value).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_deserialize_seq_empty() {
+        let value = [0 as u8, 0 as u8, 0 as u8, 0 as u8];
+        let actual: Vec<u8> = from_bytes(&value).unwrap();
+        assert!(actual.is_empty());
+    }
+
+    #[test]
+    fn test_deserialize_seq_element_overrun() {
+        let value = [
+            0 as u8, 7 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 2 as u8, '1' as u8,
+            '1' as u8, 0 as u8, 0 as u8,
+        ];
+        let mut deserializer = Deserializer::from_bytes(&value);
+        let actual = Vec::<u8>::deserialize(&mut deserializer);
+        assert_eq!(&ErrorKind::Eof, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_seq_truncated_element_length() {
+        let value = [
+            0 as u8, 5 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 0 as u8, 0 as u8,
+        ];
+        let mut deserializer = Deserializer::from_bytes(&value);
+        let actual = Vec::<u8>::deserialize(&mut deserializer);
+        assert_eq!(&ErrorKind::Eof, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_tuple() {
+        let value = [
+            0 as u8, 7 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 1 as u8, 'a' as u8,
+            0 as u8, 0 as u8,
+        ];
+        let expected = (10, "a");
+        let actual: (u8, &str) = from_bytes(&value).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_deserialize_tuple_too_short() {
+        let value = [
+            0 as u8, 4 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 0 as u8,
+        ];
+        let mut deserializer = Deserializer::from_bytes(&value);
+        let actual = <(u8, u8)>::deserialize(&mut deserializer);
+        assert_eq!(
+            &ErrorKind::Message("invalid length 1, expected a tuple of size 2".to_string()),
+            actual.unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_tuple_too_long() {
+        let value = [
+            0 as u8, 12 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 2 as u8, '1' as u8,
+            '1' as u8, 0 as u8, 2 as u8, '1' as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+        let mut deserializer = Deserializer::from_bytes(&value);
+        let actual = <(u8, u8)>::deserialize(&mut deserializer);
+        assert_eq!(
+            &ErrorKind::Message("invalid length 3, expected a tuple of size 2".to_string()),
+            actual.unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_tuple_too_long_boxes() {
+        #[derive(Debug, Deserialize)]
+        struct Item {
+            #[allow(dead_code)]
+            a: u8,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct TestStruct {
+            #[allow(dead_code)]
+            items: (Item, Item),
+        }
+
+        let mut value = vec![
+            0 as u8, 5 as u8, 'i' as u8, 't' as u8, 'e' as u8, 'm' as u8, 's' as u8, 0 as u8,
+            24 as u8,
+        ];
+        for _ in 0..3 {
+            value.extend([
+                0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+            ]);
+        }
+        value.extend([0 as u8, 0 as u8]);
+        let actual = from_bytes::<TestStruct>(&value);
+        assert_eq!(
+            &ErrorKind::Message("invalid length 3, expected a tuple of size 2".to_string()),
+            actual.unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_truncated() {
+        #[derive(Debug, Deserialize)]
+        struct TestStruct {
+            #[allow(dead_code)]
+            value: usize,
+            #[allow(dead_code)]
+            name: String,
+        }
+
+        let value = [
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            3 as u8, '3' as u8, '8' as u8, '3' as u8, 0 as u8, 4 as u8, 'n' as u8, 'a' as u8,
+            'm' as u8, 'e' as u8, 0 as u8, 7 as u8, 'a' as u8, 'n' as u8, '-' as u8, 'n' as u8,
+            'a' as u8, 'm' as u8, 'e' as u8, 0 as u8, 0 as u8,
+        ];
+        for end in 0..value.len() - 1 {
+            let actual: Result<TestStruct> = from_bytes(&value[..end]);
+            assert_eq!(&ErrorKind::Eof, actual.unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn test_deserialize_bad_integer() {
+        let value = [
+            0 as u8, 3 as u8, 'a' as u8, 'b' as u8, 'c' as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: Result<u64> = from_bytes(&value);
+        assert_eq!(&ErrorKind::BadData, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_integer_overflow() {
+        let value = [
+            0 as u8, 3 as u8, '2' as u8, '5' as u8, '6' as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: Result<u8> = from_bytes(&value);
+        assert_eq!(&ErrorKind::BadData, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_bad_utf8() {
+        let value = [0 as u8, 2 as u8, 0xc3 as u8, 0x28 as u8, 0 as u8, 0 as u8];
+        let actual: Result<String> = from_bytes(&value);
+        assert_eq!(&ErrorKind::BadData, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_empty() {
+        let actual: Result<String> = from_bytes(&[]);
+        assert_eq!(&ErrorKind::Eof, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_recursion_limit() {
+        #[derive(Debug, Deserialize)]
+        struct Nested(#[allow(dead_code)] Vec<Nested>);
+
+        let mut value = vec![0 as u8, 0 as u8];
+        for _ in 0..200 {
+            let length = value.len() as u16;
+            value.splice(0..0, length.to_be_bytes());
+        }
+        value.extend([0 as u8, 0 as u8]);
+
+        let actual: Result<Nested> = from_bytes(&value);
+        assert_eq!(
+            &ErrorKind::RecursionLimitExceeded,
+            actual.unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_key_too_long() {
+        let mut value = vec![1 as u8, 0 as u8];
+        value.extend(vec!['x' as u8; 256]);
+        value.extend([0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8]);
+
+        let actual: Result<HashMap<String, u8>> = from_bytes(&value);
+        assert_eq!(&ErrorKind::KeyTooLong, actual.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_deserialize_error_context() {
+        #[derive(Debug, Deserialize)]
+        struct NestedStruct {
+            #[allow(dead_code)]
+            inner: u8,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct TestStruct {
+            #[allow(dead_code)]
+            value: usize,
+            #[allow(dead_code)]
+            nested: NestedStruct,
+        }
+
+        let value = [
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            2 as u8, '1' as u8, '0' as u8, 0 as u8, 6 as u8, 'n' as u8, 'e' as u8, 's' as u8,
+            't' as u8, 'e' as u8, 'd' as u8, 0 as u8, 14 as u8, 0 as u8, 5 as u8, 'i' as u8,
+            'n' as u8, 'n' as u8, 'e' as u8, 'r' as u8, 0 as u8, 3 as u8, 'a' as u8, 'b' as u8,
+            'c' as u8, 0 as u8, 0 as u8, 0 as u8, 0 as u8,
+        ];
+
+        let actual: Result<TestStruct> = from_bytes(&value);
+        let error = actual.unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some(30), error.offset());
+        assert_eq!(Some("nested.inner"), error.key());
+        assert_eq!(Some("u8"), error.expected());
+        assert_eq!(Some(&b"abc"[..]), error.value());
+        assert_eq!(
+            "Error: Bad data for key `nested.inner` at byte 30, expected u8, found \"abc\"",
+            error.to_string()
+        );
+
+        // A key following a nested struct is back in the outer box.
+        #[derive(Debug, Deserialize)]
+        struct FollowedStruct {
+            #[allow(dead_code)]
+            nested: NestedStruct,
+            #[allow(dead_code)]
+            after: u8,
+        }
+
+        let value = [
+            0 as u8, 6 as u8, 'n' as u8, 'e' as u8, 's' as u8, 't' as u8, 'e' as u8, 'd' as u8,
+            0 as u8, 12 as u8, 0 as u8, 5 as u8, 'i' as u8, 'n' as u8, 'n' as u8, 'e' as u8,
+            'r' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8, 5 as u8, 'a' as u8,
+            'f' as u8, 't' as u8, 'e' as u8, 'r' as u8, 0 as u8, 3 as u8, 'x' as u8, 'y' as u8,
+            'z' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let actual: Result<FollowedStruct> = from_bytes(&value);
+        let error = actual.unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some(31), error.offset());
+        assert_eq!(Some("after"), error.key());
+        assert_eq!(Some(&b"xyz"[..]), error.value());
+    }
+
+    #[test]
+    fn test_deserialize_error_context_in_seq() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 8 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8,
+            0 as u8, 2 as u8, '1' as u8, 'x' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let actual: Result<HashMap<String, Vec<u8>>> = from_bytes(&value);
+        let error = actual.unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some(11), error.offset());
+        assert_eq!(Some("a[1]"), error.key());
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8,
+            1 as u8, 'a' as u8, 0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let mut reader = io::Cursor::new(&value[..]);
+        let first: HashMap<String, u8> = from_reader(&mut reader).unwrap();
+        assert_eq!(Some(&1), first.get("a"));
+        assert_eq!(8, reader.position());
+
+        let second: HashMap<String, u8> = from_reader(&mut reader).unwrap();
+        assert_eq!(Some(&2), second.get("a"));
+        assert_eq!(16, reader.position());
+
+        let third: Result<HashMap<String, u8>> = from_reader(&mut reader);
+        assert_eq!(&ErrorKind::Eof, third.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_from_reader_truncated() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 3 as u8, '1' as u8, '0' as u8,
+        ];
+
+        let actual: Result<HashMap<String, u8>> = from_reader(&value[..]);
+        let error = actual.unwrap_err();
+        assert_eq!(&ErrorKind::Eof, error.kind());
+        assert_eq!(Some(5), error.offset());
+    }
+
+    #[test]
+    fn test_stream_deserializer() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8,
+            1 as u8, 'a' as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 0 as u8, 0 as u8, 1 as u8,
+            'a' as u8, 0 as u8, 1 as u8, '3' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let mut stream = StreamDeserializer::<_, HashMap<&str, u8>>::from_bytes(&value);
+        assert_eq!(Some(&1), stream.next().unwrap().unwrap().get("a"));
+        assert_eq!(8, stream.byte_offset());
+
+        let error = stream.next().unwrap().unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some(13), error.offset());
+        assert_eq!(16, stream.byte_offset());
+
+        assert_eq!(Some(&3), stream.next().unwrap().unwrap().get("a"));
+        assert_eq!(24, stream.byte_offset());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_deserializer_truncated() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8,
+            1 as u8, 'a' as u8, 0 as u8,
+        ];
+
+        let mut stream = StreamDeserializer::<_, HashMap<&str, u8>>::from_bytes(&value);
+        assert!(stream.next().unwrap().is_ok());
+
+        let error = stream.next().unwrap().unwrap_err();
+        assert_eq!(&ErrorKind::Eof, error.kind());
+        assert_eq!(Some(11), error.offset());
+        assert_eq!(8, stream.byte_offset());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_deserializer_from_reader() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8,
+            1 as u8, 'a' as u8, 0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8, 0 as u8,
+        ];
+
+        let mut stream = StreamDeserializer::<_, HashMap<String, u8>>::from_reader(&value[..]);
+        assert_eq!(Some(&1), stream.next().unwrap().unwrap().get("a"));
+        assert_eq!(Some(&2), stream.next().unwrap().unwrap().get("a"));
+        assert_eq!(16, stream.byte_offset());
+
+        let error = stream.next().unwrap().unwrap_err();
+        assert_eq!(&ErrorKind::Eof, error.kind());
+        assert_eq!(Some(16), error.offset());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_deserializer_from_reader_clean_end() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let stream = StreamDeserializer::<_, HashMap<String, u8>>::from_reader(&value[..]);
+        let boxes: Vec<_> = stream.collect::<Result<_>>().unwrap();
+        assert_eq!(1, boxes.len());
+    }
+
+    #[test]
+    fn test_deserialize_any() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 2 as u8, '8' as u8, '3' as u8, 0 as u8, 1 as u8,
+            'b' as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: serde_json::Value = from_bytes(&value).unwrap();
+        assert_eq!(serde_json::json!({"a": "83", "b": "x"}), actual);
+    }
+
+    #[test]
+    fn test_deserialize_any_nested_box() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 8 as u8, 0 as u8, 1 as u8, 'b' as u8, 0 as u8,
+            1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8, 1 as u8, 'c' as u8, 0 as u8, 2 as u8,
+            0 as u8, 'x' as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: serde_json::Value = from_bytes(&value).unwrap();
+        assert_eq!(serde_json::json!({"a": {"b": "1"}, "c": "\u{0}x"}), actual);
+    }
+
+    #[test]
+    fn test_deserialize_ignored_any() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            a: u8,
+            c: u8,
+        }
+
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'b' as u8,
+            0 as u8, 2 as u8, 0xff as u8, 0xfe as u8, 0 as u8, 1 as u8, 'c' as u8, 0 as u8,
+            1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+        let expected = TestStruct { a: 1, c: 2 };
+        assert_eq!(expected, from_bytes(&value).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_untagged() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        #[serde(untagged)]
+        enum TestEnum {
+            Text(String),
+            Bytes(serde_bytes::ByteBuf),
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            a: TestEnum,
+            b: TestEnum,
+        }
+
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 1 as u8, 'b' as u8,
+            0 as u8, 1 as u8, 0xff as u8, 0 as u8, 0 as u8,
+        ];
+        let expected = TestStruct {
+            a: TestEnum::Text("x".to_string()),
+            b: TestEnum::Bytes(serde_bytes::ByteBuf::from(vec![0xff])),
+        };
+        assert_eq!(expected, from_bytes(&value).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_option() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            a: Option<u8>,
+            b: Option<u8>,
+            c: u8,
+        }
+
+        let value = [
+            0 as u8, 1 as u8, 'b' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'c' as u8,
+            0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+        let expected = TestStruct {
+            a: None,
+            b: Some(1),
+            c: 2,
+        };
+        assert_eq!(expected, from_bytes(&value).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_missing_key() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct TestStruct {
+            a: Option<u8>,
+            c: u8,
+        }
+
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+        ];
+        let error = from_bytes::<TestStruct>(&value).unwrap_err();
+        assert_eq!(&ErrorKind::MissingKey("c"), error.kind());
+        assert_eq!("Error: Missing key `c` at byte 6", error.to_string());
+    }
+
+    #[test]
+    fn test_deserialize_bytes_borrowed() {
+        #[derive(Deserialize)]
+        struct TestStruct<'a> {
+            #[serde(with = "serde_bytes")]
+            a: &'a [u8],
+        }
+
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 3 as u8, 0xff as u8, 0 as u8, 0xfe as u8,
+            0 as u8, 0 as u8,
+        ];
+        let actual: TestStruct = from_bytes(&value).unwrap();
+        assert_eq!(&[0xff, 0, 0xfe], actual.a);
+        assert!(std::ptr::eq(&value[5], &actual.a[0]));
+    }
+
+    #[test]
+    fn test_deserialize_byte_buf() {
+        let value = [
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 2 as u8, 0xc3 as u8, 0x28 as u8, 0 as u8, 0 as u8,
+        ];
+        let actual: HashMap<String, serde_bytes::ByteBuf> = from_bytes(&value).unwrap();
+        assert_eq!(&[0xc3, 0x28], &actual["a"][..]);
+    }
+
+    #[test]
+    fn test_deserialize_twisted_floats() {
+        // As written by Twisted's `amp.Float`.
+        let floats = [
+            ("0.1", 0.1),
+            ("1e+100", 1e100),
+            ("-0.0", -0.0),
+            ("1e+16", 1e16),
+            ("1.5e-05", 1.5e-5),
+            ("inf", f64::INFINITY),
+            ("-inf", f64::NEG_INFINITY),
+            ("5e-324", 5e-324),
+            ("1.7976931348623157e+308", f64::MAX),
+        ];
+        for (string, expected) in floats {
+            let mut value = vec![0 as u8, string.len() as u8];
+            value.extend(string.as_bytes());
+            value.extend([0 as u8, 0 as u8]);
+            let actual: f64 = from_bytes(&value).unwrap();
+            assert_eq!(expected, actual);
+            assert_eq!(expected.is_sign_negative(), actual.is_sign_negative());
+        }
+
+        let value = [
+            0 as u8, 3 as u8, 'n' as u8, 'a' as u8, 'n' as u8, 0 as u8, 0 as u8,
+        ];
+        assert!(from_bytes::<f64>(&value).unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_deserialize_amp_list() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Item {
+            a: u8,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            items: Vec<Item>,
+        }
+
+        let value = [
+            0 as u8, 5 as u8, 'i' as u8, 't' as u8, 'e' as u8, 'm' as u8, 's' as u8, 0 as u8,
+            16 as u8, 0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8, 0 as u8,
+            0 as u8,
+        ];
+        let expected = TestStruct {
+            items: vec![Item { a: 1 }, Item { a: 2 }],
+        };
+        assert_eq!(expected, from_bytes(&value).unwrap());
+    }
 }
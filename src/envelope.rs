@@ -0,0 +1,315 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::amp_box::AmpBox;
+use crate::box_de::from_box;
+use crate::box_ser::to_box;
+use crate::command::Command;
+use crate::de::from_bytes;
+use crate::error::{Error, ErrorKind, Result};
+use crate::ser::to_amp;
+
+// The keys AMP itself puts in a box, alongside a command's arguments or
+// response. No argument or response may use them.
+pub const RESERVED_KEYS: [&str; 6] = [
+    "_command",
+    "_ask",
+    "_answer",
+    "_error",
+    "_error_code",
+    "_error_description",
+];
+
+// A command being sent: its arguments, along with the name of the command
+// and, when an answer is wanted, the `_ask` tag the answer will refer to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CommandBox<T> {
+    pub command: String,
+    pub ask: Option<String>,
+    pub arguments: T,
+}
+
+// A successful answer to the command asked with the tag `answer`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AnswerBox<T> {
+    pub answer: String,
+    pub response: T,
+}
+
+// A failed answer to the command asked with the tag `error`. The code is one
+// of the command's declared errors, or Twisted's `UNKNOWN` or `UNHANDLED`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorBox {
+    pub error: String,
+    pub code: String,
+    pub description: String,
+}
+
+impl<T> CommandBox<T> {
+    // A box for the command `C`, tagged with `ask` if it requires an answer.
+    pub fn new<C>(ask: impl Into<String>, arguments: T) -> Self
+    where
+        C: Command<Arguments = T>,
+    {
+        CommandBox {
+            command: C::NAME.to_string(),
+            ask: if C::REQUIRES_ANSWER {
+                Some(ask.into())
+            } else {
+                None
+            },
+            arguments,
+        }
+    }
+}
+
+impl<T> CommandBox<T>
+where
+    T: Serialize,
+{
+    pub fn to_amp(&self) -> Result<Vec<u8>> {
+        let mut control = AmpBox::new();
+        control.append("_command", self.command.as_str());
+        if let Some(ask) = &self.ask {
+            control.append("_ask", ask.as_str());
+        }
+        to_envelope(control, &self.arguments)
+    }
+}
+
+impl<T> CommandBox<T>
+where
+    T: DeserializeOwned,
+{
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (mut control, arguments) = from_envelope(bytes)?;
+        Ok(CommandBox {
+            command: required(&mut control, "_command")?,
+            ask: optional(&mut control, "_ask")?,
+            arguments,
+        })
+    }
+
+    // Like `from_bytes`, for a box which must be for the command `C`.
+    pub fn from_bytes_for<C>(bytes: &[u8]) -> Result<Self>
+    where
+        C: Command<Arguments = T>,
+    {
+        let (mut control, body) = split_envelope(bytes)?;
+        let command = required(&mut control, "_command")?;
+        if command != C::NAME {
+            return Err(Error::new(ErrorKind::BadData)
+                .with_value(C::NAME, command.as_bytes())
+                .with_key(b"_command"));
+        }
+        Ok(CommandBox {
+            command,
+            ask: optional(&mut control, "_ask")?,
+            arguments: from_box(body)?,
+        })
+    }
+}
+
+impl<T> AnswerBox<T>
+where
+    T: Serialize,
+{
+    pub fn to_amp(&self) -> Result<Vec<u8>> {
+        let mut control = AmpBox::new();
+        control.append("_answer", self.answer.as_str());
+        to_envelope(control, &self.response)
+    }
+}
+
+impl<T> AnswerBox<T>
+where
+    T: DeserializeOwned,
+{
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (mut control, response) = from_envelope(bytes)?;
+        Ok(AnswerBox {
+            answer: required(&mut control, "_answer")?,
+            response,
+        })
+    }
+}
+
+impl ErrorBox {
+    pub fn to_amp(&self) -> Result<Vec<u8>> {
+        let mut control = AmpBox::new();
+        control.append("_error", self.error.as_str());
+        control.append("_error_code", self.code.as_str());
+        control.append("_error_description", self.description.as_str());
+        to_amp(&control)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (mut control, _) = split_envelope(bytes)?;
+        Ok(ErrorBox {
+            error: required(&mut control, "_error")?,
+            code: required(&mut control, "_error_code")?,
+            description: required(&mut control, "_error_description")?,
+        })
+    }
+}
+
+// The control keys come first, followed by the keys of the body, which is
+// encoded just as `to_amp` would encode it on its own.
+fn to_envelope<T>(mut control: AmpBox, body: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    let body = to_box(body)?;
+    for (key, value) in body.iter() {
+        if RESERVED_KEYS
+            .iter()
+            .any(|reserved| reserved.as_bytes() == key)
+        {
+            return Err(Error::new(ErrorKind::ReservedKey).with_key(key));
+        }
+        control.append(key, value);
+    }
+    to_amp(&control)
+}
+
+// Splits the control keys out of a box, leaving the keys of the body.
+fn split_envelope(bytes: &[u8]) -> Result<(AmpBox, AmpBox)> {
+    let mut body: AmpBox = from_bytes(bytes)?;
+    let mut control = AmpBox::new();
+    for key in RESERVED_KEYS {
+        if let Some(value) = body.remove(key) {
+            control.append(key, value);
+        }
+    }
+    Ok((control, body))
+}
+
+// Splits the control keys out of a box, and decodes what's left as the body.
+fn from_envelope<T>(bytes: &[u8]) -> Result<(AmpBox, T)>
+where
+    T: DeserializeOwned,
+{
+    let (control, body) = split_envelope(bytes)?;
+    Ok((control, from_box(body)?))
+}
+
+fn optional(control: &mut AmpBox, key: &'static str) -> Result<Option<String>> {
+    let value = control.get_str(key)?.map(str::to_string);
+    control.remove(key);
+    Ok(value)
+}
+
+fn required(control: &mut AmpBox, key: &'static str) -> Result<String> {
+    optional(control, key)?.ok_or_else(|| Error::new(ErrorKind::MissingKey(key)))
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct SumArguments {
+        a: i64,
+        b: i64,
+    }
+
+    struct Sum;
+
+    impl Command for Sum {
+        const NAME: &'static str = "Sum";
+        type Arguments = SumArguments;
+        type Response = AmpBox;
+        type Error = std::convert::Infallible;
+    }
+
+    struct Notify;
+
+    impl Command for Notify {
+        const NAME: &'static str = "Notify";
+        const REQUIRES_ANSWER: bool = false;
+        type Arguments = SumArguments;
+        type Response = AmpBox;
+        type Error = std::convert::Infallible;
+    }
+
+    #[test]
+    fn test_command_box() {
+        let command = CommandBox::new::<Sum>("1", SumArguments { a: 13, b: 81 });
+        let bytes = command.to_amp().unwrap();
+        let amp_box: AmpBox = from_bytes(&bytes).unwrap();
+        let keys: Vec<&[u8]> = amp_box.keys().collect();
+        assert_eq!(vec![&b"_command"[..], b"_ask", b"a", b"b"], keys);
+        assert_eq!(Some("Sum"), amp_box.get_str("_command").unwrap());
+        assert_eq!(command, CommandBox::from_bytes(&bytes).unwrap());
+        assert_eq!(command, CommandBox::from_bytes_for::<Sum>(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_command_box_wrong_command() {
+        let command = CommandBox::new::<Notify>("1", SumArguments { a: 13, b: 81 });
+        let bytes = command.to_amp().unwrap();
+        let error = CommandBox::from_bytes_for::<Sum>(&bytes).unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some("_command"), error.key());
+        assert_eq!(Some("Sum"), error.expected());
+        assert_eq!(Some(&b"Notify"[..]), error.value());
+    }
+
+    #[test]
+    fn test_command_box_no_answer() {
+        let command = CommandBox::new::<Notify>("1", SumArguments { a: 13, b: 81 });
+        assert_eq!(None, command.ask);
+        let bytes = command.to_amp().unwrap();
+        let amp_box: AmpBox = from_bytes(&bytes).unwrap();
+        assert!(!amp_box.contains_key("_ask"));
+        assert_eq!(command, CommandBox::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_command_box_missing_command() {
+        let bytes = to_amp(&SumArguments { a: 13, b: 81 }).unwrap();
+        let error = CommandBox::<SumArguments>::from_bytes(&bytes).unwrap_err();
+        assert_eq!(&ErrorKind::MissingKey("_command"), error.kind());
+    }
+
+    #[test]
+    fn test_answer_box() {
+        let mut response = AmpBox::new();
+        response.append("total", "94");
+        let answer = AnswerBox {
+            answer: "1".to_string(),
+            response,
+        };
+        let bytes = answer.to_amp().unwrap();
+        assert_eq!(answer, AnswerBox::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_error_box() {
+        let error = ErrorBox {
+            error: "1".to_string(),
+            code: "UNKNOWN".to_string(),
+            description: "Unknown Error".to_string(),
+        };
+        let bytes = error.to_amp().unwrap();
+        assert_eq!(error, ErrorBox::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_reserved_key() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            _ask: u8,
+        }
+
+        let answer = AnswerBox {
+            answer: "1".to_string(),
+            response: TestStruct { _ask: 1 },
+        };
+        let error = answer.to_amp().unwrap_err();
+        assert_eq!(&ErrorKind::ReservedKey, error.kind());
+        assert_eq!(Some("_ask"), error.key());
+    }
+}
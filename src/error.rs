@@ -1,49 +1,248 @@
 use std::fmt::{self, Display};
+use std::io;
 
 use serde::{de, ser};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// Boxed, so that `Result<T>` stays small on the happy path.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub enum Error {
+pub struct Error {
+    err: Box<ErrorImpl>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ErrorImpl {
+    kind: ErrorKind,
+    offset: Option<usize>,
+    key: Option<String>,
+    expected: Option<String>,
+    value: Option<Vec<u8>>,
+}
+
+// More kinds of error may be added as the protocol is filled out, so a match
+// on the kind needs a wildcard arm.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorKind {
     Message(String),
     Eof,
     TrailingCharacters,
     BadData,
+    KeyMustBeAString,
+    KeyTooLong,
+    EmptyKey,
+    MissingKey(&'static str),
+    ReservedKey,
+    ValueTooLong,
+    RecursionLimitExceeded,
+    UnsupportedType(&'static str),
+    UnknownAnswer,
+    DuplicateAnswer,
+    Io(io::ErrorKind),
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Error {
+            err: Box::new(ErrorImpl {
+                kind,
+                offset: None,
+                key: None,
+                expected: None,
+                value: None,
+            }),
+        }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.err.kind
+    }
+
+    // The byte offset into the box where the error occurred, when decoding.
+    pub fn offset(&self) -> Option<usize> {
+        self.err.offset
+    }
+
+    // The path of the key whose value caused the error, e.g. `nested.inner`,
+    // or `values[2]` for the third element of a sequence.
+    pub fn key(&self) -> Option<&str> {
+        self.err.key.as_deref()
+    }
+
+    // The Rust type a value was being decoded as.
+    pub fn expected(&self) -> Option<&str> {
+        self.err.expected.as_deref()
+    }
+
+    // The raw value that couldn't be decoded.
+    pub fn value(&self) -> Option<&[u8]> {
+        self.err.value.as_deref()
+    }
+
+    pub(crate) fn with_offset(mut self, offset: usize) -> Self {
+        if self.err.offset.is_none() {
+            self.err.offset = Some(offset);
+        }
+        self
+    }
+
+    // Errors bubble up from the innermost value, so each enclosing key is
+    // prepended to the path.
+    pub(crate) fn with_key(mut self, key: &[u8]) -> Self {
+        let key = String::from_utf8_lossy(key);
+        self.err.key = Some(match self.err.key.take() {
+            Some(path) if path.starts_with('[') => format!("{}{}", key, path),
+            Some(path) => format!("{}.{}", key, path),
+            None => key.into_owned(),
+        });
+        self
+    }
+
+    pub(crate) fn with_index(mut self, index: usize) -> Self {
+        self.err.key = Some(match self.err.key.take() {
+            Some(path) if path.starts_with('[') => format!("[{}]{}", index, path),
+            Some(path) => format!("[{}].{}", index, path),
+            None => format!("[{}]", index),
+        });
+        self
+    }
+
+    pub(crate) fn with_value(mut self, expected: &str, value: &[u8]) -> Self {
+        self.err.expected = Some(expected.to_string());
+        self.err.value = Some(value.to_vec());
+        self
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind)
+    }
+}
+
+// An unexpected EOF from a reader is just a truncated box.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Error::new(ErrorKind::Eof),
+            kind => Error::new(ErrorKind::Io(kind)),
+        }
+    }
 }
 
 impl ser::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        Error::new(ErrorKind::Message(msg.to_string()))
     }
 }
 
 impl de::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        Error::new(ErrorKind::Message(msg.to_string()))
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Error::new(ErrorKind::MissingKey(field))
     }
 }
 
-impl Display for Error {
+impl Display for ErrorKind {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Message(message) => formatter.write_str(&format!("Error: {}", message)),
-            Error::BadData => formatter.write_str("Error: Bad data"),
-            Error::Eof => formatter.write_str("Error: Unexpected EOF"),
-            Error::TrailingCharacters => {
-                formatter.write_str("Error: Unexpected trailing characters")
+            ErrorKind::Message(message) => formatter.write_str(message),
+            ErrorKind::BadData => formatter.write_str("Bad data"),
+            ErrorKind::Eof => formatter.write_str("Unexpected EOF"),
+            ErrorKind::TrailingCharacters => formatter.write_str("Unexpected trailing characters"),
+            ErrorKind::KeyMustBeAString => formatter.write_str("Key must be a string"),
+            ErrorKind::KeyTooLong => formatter.write_str("Key longer than 255 bytes"),
+            ErrorKind::EmptyKey => formatter.write_str("Key must not be empty"),
+            ErrorKind::MissingKey(key) => write!(formatter, "Missing key `{}`", key),
+            ErrorKind::ReservedKey => formatter.write_str("Key is reserved by AMP"),
+            ErrorKind::ValueTooLong => formatter.write_str("Value longer than 65535 bytes"),
+            ErrorKind::RecursionLimitExceeded => formatter.write_str("Recursion limit exceeded"),
+            ErrorKind::UnsupportedType(name) => {
+                formatter.write_str(&format!("Unsupported type: {}", name))
+            }
+            ErrorKind::UnknownAnswer => formatter.write_str("Answer to a command never asked"),
+            ErrorKind::DuplicateAnswer => {
+                formatter.write_str("Answer to a command already answered")
             }
+            ErrorKind::Io(kind) => formatter.write_str(&format!("I/O error: {}", kind)),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Error: {}", self.err.kind)?;
+        if let Some(key) = &self.err.key {
+            write!(formatter, " for key `{}`", key)?;
+        }
+        if let Some(offset) = self.err.offset {
+            write!(formatter, " at byte {}", offset)?;
         }
+        if let Some(expected) = &self.err.expected {
+            write!(formatter, ", expected {}", expected)?;
+        }
+        if let Some(value) = &self.err.value {
+            write!(formatter, ", found \"{}\"", value.escape_ascii())?;
+        }
+        Ok(())
     }
 }
 
 impl std::error::Error for Error {
     fn description(&self) -> &str {
-        match *self {
-            Error::Message(ref msg) => msg,
-            Error::Eof => "unexpected end of file",
-            Error::TrailingCharacters => "characters after the end",
-            Error::BadData => "bad or malformed data",
+        match self.err.kind {
+            ErrorKind::Message(ref msg) => msg,
+            ErrorKind::Eof => "unexpected end of file",
+            ErrorKind::TrailingCharacters => "characters after the end",
+            ErrorKind::BadData => "bad or malformed data",
+            ErrorKind::KeyMustBeAString => "key must be a string",
+            ErrorKind::KeyTooLong => "key longer than 255 bytes",
+            ErrorKind::EmptyKey => "key must not be empty",
+            ErrorKind::MissingKey(_) => "missing key",
+            ErrorKind::ReservedKey => "key reserved by AMP",
+            ErrorKind::ValueTooLong => "value longer than 65535 bytes",
+            ErrorKind::RecursionLimitExceeded => "recursion limit exceeded",
+            ErrorKind::UnsupportedType(_) => "unsupported type",
+            ErrorKind::UnknownAnswer => "answer to a command never asked",
+            ErrorKind::DuplicateAnswer => "answer to a command already answered",
+            ErrorKind::Io(_) => "I/O error",
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = Error::new(ErrorKind::BadData)
+            .with_value("u8", b"abc")
+            .with_offset(12)
+            .with_key(b"inner")
+            .with_key(b"nested");
+        assert_eq!(
+            "Error: Bad data for key `nested.inner` at byte 12, expected u8, found \"abc\"",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_display_message() {
+        let error = Error::new(ErrorKind::Eof);
+        assert_eq!("Error: Unexpected EOF", error.to_string());
+    }
+
+    #[test]
+    fn test_key_path() {
+        let error = Error::new(ErrorKind::Eof)
+            .with_key(b"inner")
+            .with_index(2)
+            .with_key(b"values");
+        assert_eq!(Some("values[2].inner"), error.key());
+    }
+}
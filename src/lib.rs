@@ -1,13 +1,37 @@
+// Lets the code generated by the derive macros name this crate from its own
+// tests.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as serde_amp;
+
+mod amp_box;
+mod box_de;
+mod box_ser;
+mod command;
 mod de;
+mod envelope;
 mod error;
 mod ser;
+mod session;
+pub mod types;
 
-pub use de::from_bytes;
-pub use error::Error;
-pub use ser::to_amp;
+pub use amp_box::AmpBox;
+pub use box_de::from_box;
+pub use box_ser::to_box;
+pub use command::{Command, CommandError};
+pub use de::{from_bytes, from_reader, IoRead, StreamDeserializer};
+pub use envelope::{AnswerBox, CommandBox, ErrorBox, RESERVED_KEYS};
+pub use error::{Error, ErrorKind};
+pub use ser::{to_amp, to_writer};
+#[cfg(feature = "derive")]
+pub use serde_amp_derive::{AmpCommand, AmpError};
+pub use session::{
+    Answer, ErrorAnswer, Event, IncomingCommand, Session, UNHANDLED_ERROR_CODE, UNKNOWN_ERROR_CODE,
+};
 
 #[cfg(test)]
 mod test {
+    use std::collections::{BTreeMap, HashMap};
+
     use serde::{Deserialize, Serialize};
 
     use super::*;
@@ -28,4 +52,628 @@ mod test {
         assert_eq!(data.value, result.value);
         assert_eq!(data.name, result.name);
     }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum TestEnum {
+        Unit,
+        Newtype(usize),
+        Tuple(usize, String),
+        Struct { value: usize, name: String },
+    }
+
+    #[test]
+    fn test_enum_serialize_deserialize() {
+        let variants = vec![
+            TestEnum::Unit,
+            TestEnum::Newtype(83),
+            TestEnum::Tuple(83, "Kilroy".to_string()),
+            TestEnum::Struct {
+                value: 83,
+                name: "Kilroy".to_string(),
+            },
+        ];
+        for data in variants {
+            let result: TestEnum = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+            assert_eq!(data, result);
+        }
+    }
+
+    #[test]
+    fn test_nested_enum_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            first: TestEnum,
+            second: TestEnum,
+            name: String,
+        }
+
+        let data = TestStruct {
+            first: TestEnum::Struct {
+                value: 83,
+                name: "Kilroy".to_string(),
+            },
+            second: TestEnum::Unit,
+            name: "was here".to_string(),
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_nested_struct_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Inner {
+            x: usize,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Middle {
+            inner: Inner,
+            name: String,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            middle: Middle,
+            b: usize,
+        }
+
+        let data = TestStruct {
+            middle: Middle {
+                inner: Inner { x: 1 },
+                name: "Kilroy".to_string(),
+            },
+            b: 2,
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+
+        let amp_box = to_box(&data).unwrap();
+        assert_eq!(Some(2), amp_box.get_int("b").unwrap());
+        assert_eq!(data, from_box::<TestStruct>(amp_box).unwrap());
+    }
+
+    #[test]
+    fn test_writer_reader_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            variant: TestEnum,
+            values: Vec<usize>,
+            name: String,
+        }
+
+        let first = TestStruct {
+            variant: TestEnum::Tuple(83, "Kilroy".to_string()),
+            values: vec![1, 2],
+            name: "was here".to_string(),
+        };
+        let second = TestEnum::Struct {
+            value: 83,
+            name: "Kilroy".to_string(),
+        };
+        let mut writer = vec![];
+        to_writer(&mut writer, &first).unwrap();
+        to_writer(&mut writer, &second).unwrap();
+
+        let mut reader = std::io::BufReader::new(&writer[..]);
+        assert_eq!(first, from_reader::<_, TestStruct>(&mut reader).unwrap());
+        assert_eq!(second, from_reader::<_, TestEnum>(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_internally_tagged_enum_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        #[serde(tag = "type")]
+        enum TestTagged {
+            Unit,
+            Struct { name: String },
+        }
+
+        for data in [
+            TestTagged::Unit,
+            TestTagged::Struct {
+                name: "Kilroy".to_string(),
+            },
+        ] {
+            let result: TestTagged = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+            assert_eq!(data, result);
+        }
+    }
+
+    #[test]
+    fn test_internally_tagged_enum_number_field() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        #[serde(tag = "type")]
+        enum TestTagged {
+            Struct { value: u32 },
+        }
+
+        // serde buffers the field as the string it is on the wire, and won't
+        // then read it as a number.
+        let data = TestTagged::Struct { value: 83 };
+        let result: Result<TestTagged, Error> = from_bytes(&to_amp(&data).unwrap()[..]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_adjacently_tagged_enum_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        #[serde(tag = "type", content = "data")]
+        enum TestTagged {
+            Unit,
+            Newtype(usize),
+            Tuple(usize, String),
+            Struct { value: usize, name: String },
+        }
+
+        for data in [
+            TestTagged::Unit,
+            TestTagged::Newtype(83),
+            TestTagged::Tuple(83, "Kilroy".to_string()),
+            TestTagged::Struct {
+                value: 83,
+                name: "Kilroy".to_string(),
+            },
+        ] {
+            let result: TestTagged = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+            assert_eq!(data, result);
+        }
+    }
+
+    #[test]
+    fn test_untagged_enum_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        #[serde(untagged)]
+        enum TestUntagged {
+            Number(u32),
+            Text(String),
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            value: TestUntagged,
+        }
+
+        // Every value is a string on the wire, so a number can't be told
+        // apart from one, and only ever matches a string-like variant.
+        let data = TestStruct {
+            value: TestUntagged::Number(3),
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(TestUntagged::Text("3".to_string()), result.value);
+    }
+
+    #[test]
+    fn test_map_serialize_deserialize() {
+        let mut data = HashMap::new();
+        data.insert("value".to_string(), "83".to_string());
+        data.insert("name".to_string(), "Kilroy".to_string());
+
+        let result: HashMap<String, String> = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+
+        let data: BTreeMap<String, String> = data.into_iter().collect();
+        let result: BTreeMap<String, String> = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_flatten_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct NestedStruct {
+            tag: String,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            name: String,
+            #[serde(flatten)]
+            nested: NestedStruct,
+            #[serde(flatten)]
+            extra: HashMap<String, String>,
+        }
+
+        let mut extra = HashMap::new();
+        extra.insert("_extension".to_string(), "an-extension".to_string());
+        let data = TestStruct {
+            name: "Kilroy".to_string(),
+            nested: NestedStruct {
+                tag: "an-tag".to_string(),
+            },
+            extra,
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_seq_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            values: Vec<usize>,
+            names: Vec<String>,
+            nested: Vec<Vec<String>>,
+        }
+
+        let data = TestStruct {
+            values: vec![83, 0, 65535],
+            names: vec!["Kilroy".to_string(), "".to_string()],
+            nested: vec![vec![], vec!["was".to_string(), "here".to_string()]],
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_tuple_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Point(i32, i32);
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            point: Point,
+            pair: (usize, String),
+        }
+
+        let data = TestStruct {
+            point: Point(-83, 83),
+            pair: (83, "Kilroy".to_string()),
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_amp_box_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            value: usize,
+            name: String,
+        }
+
+        let data = TestStruct {
+            value: 83,
+            name: "Kilroy".to_string(),
+        };
+        let mut amp_box: AmpBox = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(Some(83), amp_box.get_int("value").unwrap());
+        amp_box.insert("name", "was here");
+
+        let result: TestStruct = from_bytes(&to_amp(&amp_box).unwrap()[..]).unwrap();
+        assert_eq!(
+            TestStruct {
+                value: 83,
+                name: "was here".to_string(),
+            },
+            result
+        );
+    }
+
+    #[test]
+    fn test_box_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            value: usize,
+            name: String,
+            values: Vec<u8>,
+            pair: (usize, String),
+            variant: TestEnum,
+        }
+
+        let data = TestStruct {
+            value: 83,
+            name: "Kilroy".to_string(),
+            values: vec![0, 65],
+            pair: (83, "was here".to_string()),
+            variant: TestEnum::Struct {
+                value: 83,
+                name: "Kilroy".to_string(),
+            },
+        };
+        let amp_box = to_box(&data).unwrap();
+        let expected: AmpBox = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(expected, amp_box);
+
+        let result: TestStruct = from_box(amp_box).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_box_enum_serialize_deserialize() {
+        let data = vec![
+            TestEnum::Newtype(83),
+            TestEnum::Tuple(83, "Kilroy".to_string()),
+            TestEnum::Struct {
+                value: 83,
+                name: "Kilroy".to_string(),
+            },
+        ];
+        for value in data {
+            let amp_box = to_box(&value).unwrap();
+            let expected: AmpBox = from_bytes(&to_amp(&value).unwrap()[..]).unwrap();
+            assert_eq!(expected, amp_box);
+
+            let result: TestEnum = from_box(amp_box).unwrap();
+            assert_eq!(value, result);
+        }
+    }
+
+    #[test]
+    fn test_option_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            value: Option<usize>,
+            name: Option<String>,
+            variant: Option<TestEnum>,
+        }
+
+        let data = TestStruct {
+            value: Some(83),
+            name: None,
+            variant: Some(TestEnum::Newtype(83)),
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+
+        let amp_box = to_box(&data).unwrap();
+        assert!(!amp_box.contains_key("name"));
+        let result: TestStruct = from_box(amp_box).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_nested_option_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            a: Option<Option<usize>>,
+            b: usize,
+        }
+
+        for a in [None, Some(Some(83))] {
+            let data = TestStruct { a, b: 1 };
+            let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+            assert_eq!(data, result);
+        }
+
+        // Leaving out `a` already means `None`, so `Some(None)` can't be told
+        // apart from it.
+        let data = TestStruct {
+            a: Some(None),
+            b: 1,
+        };
+        let error = to_amp(&data).unwrap_err();
+        assert_eq!(&ErrorKind::UnsupportedType("nested option"), error.kind());
+        assert_eq!(Some("a"), error.key());
+        let error = to_box(&data).unwrap_err();
+        assert_eq!(&ErrorKind::UnsupportedType("nested option"), error.kind());
+    }
+
+    #[test]
+    fn test_bytes_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            hash: serde_bytes::ByteBuf,
+            #[serde(with = "serde_bytes")]
+            blob: Vec<u8>,
+            chunks: Vec<serde_bytes::ByteBuf>,
+        }
+
+        let data = TestStruct {
+            hash: serde_bytes::ByteBuf::from((0..=255).collect::<Vec<u8>>()),
+            blob: vec![0; 65535],
+            chunks: vec![
+                serde_bytes::ByteBuf::from(vec![0xff, 0xfe]),
+                serde_bytes::ByteBuf::new(),
+            ],
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+
+        let result: TestStruct = from_box(to_box(&data).unwrap()).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_float_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            values: Vec<f64>,
+            single: f32,
+        }
+
+        let data = TestStruct {
+            values: vec![
+                0.1,
+                -0.0,
+                1e100,
+                1.5e-5,
+                f64::MIN_POSITIVE,
+                f64::NEG_INFINITY,
+            ],
+            single: 12.9,
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_decimal_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            amount: types::Decimal,
+            values: Vec<types::Decimal>,
+        }
+
+        let data = TestStruct {
+            amount: "12345678901234567890.123456789".parse().unwrap(),
+            values: vec![
+                "1.10".parse().unwrap(),
+                "sNaN".parse().unwrap(),
+                "-Infinity".parse().unwrap(),
+                "1E+400".parse().unwrap(),
+            ],
+        };
+        let serialized = to_amp(&data).unwrap();
+        let amp_box: AmpBox = from_bytes(&serialized[..]).unwrap();
+        assert_eq!(
+            Some("12345678901234567890.123456789"),
+            amp_box.get_str("amount").unwrap()
+        );
+        let result: TestStruct = from_bytes(&serialized[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_date_time_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            when: types::DateTime,
+        }
+
+        let data = TestStruct {
+            when: "2012-01-23T12:34:56.054321+05:30".parse().unwrap(),
+        };
+        let serialized = to_amp(&data).unwrap();
+        let amp_box: AmpBox = from_bytes(&serialized[..]).unwrap();
+        assert_eq!(
+            Some("2012-01-23T12:34:56.054321+05:30"),
+            amp_box.get_str("when").unwrap()
+        );
+        let result: TestStruct = from_bytes(&serialized[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[test]
+    fn test_amp_list_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Item {
+            name: String,
+            position: Position,
+            count: Option<usize>,
+            color: TestEnum,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Position {
+            x: i32,
+            y: i32,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        enum TestEnum {
+            Red,
+            Custom { rgb: (u8, u8, u8) },
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            items: Vec<Item>,
+            maps: Vec<BTreeMap<String, String>>,
+            empty: Vec<Item>,
+        }
+
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), "1".to_string());
+        let data = TestStruct {
+            items: vec![
+                Item {
+                    name: "Kilroy".to_string(),
+                    position: Position { x: 1, y: 2 },
+                    count: Some(83),
+                    color: TestEnum::Red,
+                },
+                Item {
+                    name: "was here".to_string(),
+                    position: Position { x: -1, y: 0 },
+                    count: None,
+                    color: TestEnum::Custom { rgb: (1, 2, 3) },
+                },
+            ],
+            maps: vec![map, BTreeMap::new()],
+            empty: vec![],
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+
+        let amp_box = to_box(&data).unwrap();
+        assert_eq!(data, from_box::<TestStruct>(amp_box).unwrap());
+    }
+
+    #[test]
+    fn test_text_serialize_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            name: types::Unicode,
+            digest: types::Bytes,
+            path: types::Path,
+        }
+
+        let data = TestStruct {
+            name: "Kilroy \u{2603}".into(),
+            digest: vec![0xff, 0, 0xfe].into(),
+            path: std::path::PathBuf::from("/home/kilroy").into(),
+        };
+        let result: TestStruct = from_bytes(&to_amp(&data).unwrap()[..]).unwrap();
+        assert_eq!(data, result);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_command() {
+        #[derive(AmpCommand, Debug, Deserialize, PartialEq, Serialize)]
+        #[amp(name = "divide", response = DivideResponse, errors = DivideError)]
+        struct Divide {
+            numerator: i64,
+            denominator: i64,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct DivideResponse {
+            result: f64,
+        }
+
+        #[derive(AmpError, Debug, PartialEq)]
+        enum DivideError {
+            #[amp(code = "ZERO_DIVISION")]
+            ZeroDivision,
+            #[amp(fatal)]
+            Overflow(String),
+        }
+
+        impl std::fmt::Display for DivideError {
+            fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match self {
+                    DivideError::ZeroDivision => formatter.write_str("division by zero"),
+                    DivideError::Overflow(description) => formatter.write_str(description),
+                }
+            }
+        }
+
+        #[derive(AmpCommand, Deserialize, Serialize)]
+        #[amp(response = AmpBox, requires_answer = false)]
+        struct Notify {}
+
+        assert_eq!("divide", Divide::NAME);
+        assert_eq!("Notify", Notify::NAME);
+        assert_eq!(None, CommandBox::new::<Notify>("1", Notify {}).ask);
+
+        let arguments = Divide {
+            numerator: 83,
+            denominator: 0,
+        };
+        let serialized = Divide::make_arguments(&arguments).unwrap();
+        assert_eq!(arguments, Divide::parse_arguments(&serialized).unwrap());
+        let command = CommandBox::new::<Divide>("1", arguments);
+        assert_eq!(Some("1".to_string()), command.ask);
+
+        let error = DivideError::ZeroDivision;
+        assert_eq!("ZERO_DIVISION", error.code());
+        assert!(!error.is_fatal());
+        assert_eq!(
+            Some(DivideError::Overflow("too big".to_string())),
+            DivideError::from_code("Overflow", "too big")
+        );
+        assert!(DivideError::from_code("Overflow", "").unwrap().is_fatal());
+        assert_eq!(None, DivideError::from_code("UNKNOWN", ""));
+    }
 }
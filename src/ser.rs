@@ -1,20 +1,75 @@
-use std::convert::TryInto;
+use std::fmt::LowerExp;
+use std::io;
+use std::mem;
 
-use byteorder::{BigEndian, WriteBytesExt};
-use serde::{ser, Serialize};
+use byteorder::{BigEndian, ByteOrder};
+use serde::ser;
+use serde::ser::Impossible;
 
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Result};
 
-fn usize_to_bytes(integer: usize) -> [u8; 2] {
-    if integer > std::u16::MAX as usize {
-        panic!("Key length in response too long");
+pub(crate) fn usize_to_bytes(integer: usize) -> Result<[u8; 2]> {
+    if integer > u16::MAX as usize {
+        return Err(Error::new(ErrorKind::ValueTooLong));
     }
 
-    let mut bytearray = Vec::with_capacity(2);
-    bytearray.write_u16::<BigEndian>(integer as u16).unwrap();
-    match bytearray.try_into() {
-        Ok(value) => value,
-        Err(err) => panic!("{:?}", err),
+    let mut bytearray = [0_u8; 2];
+    BigEndian::write_u16(&mut bytearray, integer as u16);
+    Ok(bytearray)
+}
+
+// Twisted's `amp.Float` is Python's `repr` of a float, so floats are
+// written just as Python would write them: the shortest digits that
+// round-trip, in fixed notation with at least one decimal place when the
+// decimal point is near them, and otherwise in scientific notation with a
+// signed exponent of at least two digits, e.g. `1e+100` or `1.5e-05`.
+fn float_to_string<F>(v: F) -> String
+where
+    F: Copy + Into<f64> + LowerExp,
+{
+    let float: f64 = v.into();
+    if float.is_nan() {
+        return "nan".to_string();
+    }
+    if float.is_infinite() {
+        return if float > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    // Rust's `{:e}` gives the same shortest digits, e.g. `-1.5e-5`.
+    let scientific = format!("{:e}", v);
+    let (mantissa, exponent) = scientific.split_at(scientific.find('e').unwrap_or(0));
+    let exponent: i32 = exponent[1..].parse().unwrap_or(0);
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+
+    // Where the decimal point goes, counting from the left of the digits.
+    let point = exponent + 1;
+    if point > -4 && point <= 16 {
+        let point = point.max(0) as usize;
+        if point == 0 {
+            format!(
+                "{}0.{}{}",
+                sign,
+                "0".repeat((-exponent - 1) as usize),
+                digits
+            )
+        } else if point >= digits.len() {
+            format!("{}{}{}.0", sign, digits, "0".repeat(point - digits.len()))
+        } else {
+            format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+        }
+    } else {
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}e{}{:02}",
+            sign,
+            mantissa,
+            exponent_sign,
+            exponent.abs()
+        )
     }
 }
 
@@ -24,32 +79,248 @@ struct Serializer {
     // as we may have multiple markers.
     byte_indexes: Vec<usize>,
 
+    // How deeply nested the value being serialized is. At depth zero, we are
+    // writing the box itself; anything deeper is a value inside of a box.
+    depth: usize,
+
+    // The depth of the elements of the sequence being serialized, if any.
+    // Structs and maps as elements are written as complete boxes, each with
+    // its own terminator, like Twisted's AmpList.
+    element_depth: Option<usize>,
+
+    // Where the key of each map entry being serialized starts, so that the
+    // key can be taken back out if its value turns out to be `None`.
+    key_indexes: Vec<usize>,
+
+    // Set by `serialize_none`, which writes nothing at all, so that whatever
+    // holds the value can tell it apart from a value which does.
+    none: bool,
+
+    // Set once the top-level value turns out to be a box: a struct, a map or
+    // an enum variant carrying data.
+    boxed: bool,
+
     output: Vec<u8>,
 }
 
 impl Serializer {
+    fn new() -> Self {
+        Serializer {
+            byte_indexes: vec![],
+            depth: 0,
+            element_depth: None,
+            key_indexes: vec![],
+            none: false,
+            boxed: false,
+            output: vec![],
+        }
+    }
+
     // Amp requires termination with bytes 0x00 0x00. serde doesn't *seem*
     // to have a `end`-type call for termination. This must be called
     // explicitly.
     fn end(&mut self) {
         self.output.extend(vec![0_u8, 0_u8]);
     }
+
+    // Mark the start of a value whose length isn't known until it has been
+    // written. The length is inserted by `end_length_prefix`.
+    fn start_length_prefix(&mut self) {
+        self.byte_indexes.push(self.output.len());
+    }
+
+    fn end_length_prefix(&mut self) -> Result<()> {
+        let index = match self.byte_indexes.pop() {
+            Some(index) => index,
+            None => {
+                return Err(Error::new(ErrorKind::Message(
+                    "unbalanced length prefix".to_string(),
+                )))
+            }
+        };
+
+        let count = self.output.len() - index;
+        let bytes = usize_to_bytes(count)?;
+
+        self.output.insert(index, bytes[0]);
+        self.output.insert(index + 1, bytes[1]);
+
+        Ok(())
+    }
+
+    // AMP keys are at most 255 bytes long, so the first byte of their length
+    // is always 0x00. An empty key would be read as the box's terminator.
+    fn serialize_key(&mut self, key: &str) -> Result<()> {
+        self.serialize_key_bytes(key.as_bytes())
+    }
+
+    fn serialize_key_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        if bytes.is_empty() {
+            return Err(Error::new(ErrorKind::EmptyKey));
+        }
+        if bytes.len() > u8::MAX as usize {
+            return Err(Error::new(ErrorKind::KeyTooLong));
+        }
+        self.output.extend(usize_to_bytes(bytes.len())?.iter());
+        self.output.extend(bytes);
+        Ok(())
+    }
+
+    fn serialize_nested<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        self.depth += 1;
+        let result = value.serialize(&mut *self);
+        self.depth -= 1;
+        result
+    }
+
+    // Whether the value just serialized was `None`, clearing the mark so that
+    // it isn't mistaken for that of whatever holds the value.
+    fn take_none(&mut self) -> bool {
+        mem::take(&mut self.none)
+    }
+
+    // AMP leaves optional values out by leaving out their keys. `None` writes
+    // nothing at all, so when a value turns out to be `None`, the key starting
+    // at `key_index` is taken back out too.
+    fn serialize_entry_value<T>(&mut self, key_index: usize, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        self.serialize_nested(value)?;
+        if self.take_none() {
+            self.output.truncate(key_index);
+        }
+        Ok(())
+    }
+
+    // Unlike a key, an element of a sequence can't be left out, so there is
+    // no way to write `None`.
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        let element_depth = self.element_depth.replace(self.depth + 1);
+        let result = self.serialize_nested(value);
+        self.element_depth = element_depth;
+        result?;
+        if self.take_none() {
+            return Err(Error::new(ErrorKind::UnsupportedType("option")));
+        }
+        Ok(())
+    }
+
+    // At the top level, a struct or map is the box itself. Anywhere else, it
+    // is a box of its own, complete with its terminator, which is nested as a
+    // length-prefixed value, just like a struct variant's data. Elements of a
+    // sequence go without the length prefix; see `element_depth`.
+    fn start_map(&mut self) {
+        if self.depth == 0 {
+            self.boxed = true;
+        } else if self.element_depth != Some(self.depth) {
+            self.start_length_prefix();
+        }
+    }
+
+    fn end_map(&mut self) -> Result<()> {
+        if self.depth > 0 {
+            self.end();
+            if self.element_depth != Some(self.depth) {
+                self.end_length_prefix()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Enum variants carrying data are encoded as a box with a single key, the
+    // variant name, whose value is the variant's data. At the top level, that
+    // box is the output itself. Anywhere else, the box is nested as a
+    // length-prefixed value, complete with its own terminator.
+    fn start_variant(&mut self, variant: &'static str) -> Result<()> {
+        if self.depth == 0 {
+            self.boxed = true;
+        } else {
+            self.start_length_prefix();
+        }
+        self.serialize_key(variant)?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn end_variant(&mut self) -> Result<()> {
+        self.depth -= 1;
+        if self.depth > 0 {
+            self.end();
+            self.end_length_prefix()?;
+        }
+        Ok(())
+    }
 }
 
 pub fn to_amp<T>(value: &T) -> Result<Vec<u8>>
 where
     T: ser::Serialize,
+{
+    let mut serializer = Serializer::new();
+    value.serialize(&mut serializer)?;
+    serializer.end();
+    Ok(serializer.output)
+}
+
+// The encoding of a single key, without its length prefix, as held by an
+// `AmpBox`.
+pub(crate) fn to_key<T>(key: &T) -> Result<Vec<u8>>
+where
+    T: ?Sized + ser::Serialize,
+{
+    let mut serializer = Serializer::new();
+    key.serialize(MapKeySerializer {
+        ser: &mut serializer,
+    })?;
+    Ok(serializer.output.split_off(2))
+}
+
+// The encoding of a single value inside of a box, without its length
+// prefix, as held by an `AmpBox`, or nothing at all for `None`.
+pub(crate) fn to_value<T>(value: &T) -> Result<Option<Vec<u8>>>
+where
+    T: ?Sized + ser::Serialize,
 {
     let mut serializer = Serializer {
-        byte_indexes: vec![],
-        output: vec![],
+        depth: 1,
+        ..Serializer::new()
     };
     value.serialize(&mut serializer)?;
+    if serializer.none {
+        return Ok(None);
+    }
+    Ok(Some(serializer.output.split_off(2)))
+}
+
+// As values are length-prefixed, the box is built in memory before being
+// written out in one go. Unlike `to_amp`, only boxes can be written, as
+// anything else couldn't be read back by `from_reader`, which reads up to a
+// box's terminator.
+pub fn to_writer<W, T>(mut writer: W, value: &T) -> Result<()>
+where
+    W: io::Write,
+    T: ser::Serialize,
+{
+    let mut serializer = Serializer::new();
+    value.serialize(&mut serializer)?;
+    if !serializer.boxed {
+        return Err(Error::new(ErrorKind::UnsupportedType(
+            std::any::type_name::<T>(),
+        )));
+    }
     serializer.end();
-    Ok(serializer.output)
+    writer.write_all(&serializer.output)?;
+    Ok(())
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl ser::Serializer for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -72,10 +343,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_str(&v.to_string())
     }
     fn serialize_str(self, v: &str) -> Result<()> {
-        let bytes = v.as_bytes();
-        self.output.extend(usize_to_bytes(bytes.len()).iter());
-        self.output.extend(v.as_bytes());
-        Ok(())
+        self.serialize_bytes(v.as_bytes())
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
@@ -104,29 +372,42 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_str(&v.to_string())
     }
 
+    // An `f32` is written with its own shortest digits, rather than those
+    // of the `f64` it would widen to, so 12.9 stays 12.9.
     fn serialize_f32(self, v: f32) -> Result<()> {
-        self.serialize_f64(v as f64)
+        self.serialize_str(&float_to_string(v))
     }
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.serialize_str(&v.to_string())
+        self.serialize_str(&float_to_string(v))
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
-        unimplemented!();
+    // AMP values are byte strings, so bytes are written as they are.
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.output.extend(usize_to_bytes(v.len())?.iter());
+        self.output.extend(v);
+        Ok(())
     }
 
+    // See `serialize_entry_value`.
     fn serialize_none(self) -> Result<()> {
-        self.serialize_unit()
+        self.none = true;
+        Ok(())
     }
     fn serialize_unit(self) -> Result<()> {
-        unimplemented!();
+        Err(Error::new(ErrorKind::UnsupportedType("unit")))
     }
 
+    // Leaving out a key can only say `None` once, so `Some(None)` has no
+    // encoding of its own, and would be read back as `None`.
     fn serialize_some<T>(self, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        value.serialize(self)
+        value.serialize(&mut *self)?;
+        if self.take_none() {
+            return Err(Error::new(ErrorKind::UnsupportedType("nested option")));
+        }
+        Ok(())
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
@@ -153,13 +434,18 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-        _value: &T,
+        variant: &'static str,
+        value: &T,
     ) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        unimplemented!();
+        self.start_variant(variant)?;
+        value.serialize(&mut *self)?;
+        if self.take_none() {
+            return Err(Error::new(ErrorKind::UnsupportedType("option")));
+        }
+        self.end_variant()
     }
 
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
@@ -173,35 +459,42 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_seq(Some(len))
     }
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        self.byte_indexes.push(self.output.len());
+        self.start_length_prefix();
         Ok(self)
     }
 
+    // A tuple variant's data is a sequence, so it is encoded like a ListOf.
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-        _len: usize,
+        variant: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        unimplemented!();
+        self.start_variant(variant)?;
+        ser::Serializer::serialize_seq(self, Some(len))
     }
 
     fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
         self.serialize_map(Some(len))
     }
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.start_map();
         Ok(self)
     }
 
+    // A struct variant's data is its own box, nested as a length-prefixed
+    // value under the variant name.
     fn serialize_struct_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        unimplemented!();
+        self.start_variant(variant)?;
+        self.start_length_prefix();
+        Ok(self)
     }
 
     fn is_human_readable(&self) -> bool {
@@ -209,7 +502,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl ser::SerializeSeq for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -217,94 +510,93 @@ impl<'a> ser::SerializeSeq for &'a mut Serializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        value.serialize(&mut **self)
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<()> {
-        let index = self.byte_indexes.pop().unwrap();
-
-        let count = self.output.len() - index;
-        let bytes = usize_to_bytes(count);
-
-        self.output.insert(index, bytes[0]);
-        self.output.insert(index + 1, bytes[1]);
-
-        Ok(())
+        self.end_length_prefix()
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl ser::SerializeTuple for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T>(&mut self, _value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        unimplemented!();
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<()> {
-        unimplemented!();
+        self.end_length_prefix()
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl ser::SerializeTupleStruct for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        unimplemented!();
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<()> {
-        unimplemented!();
+        self.end_length_prefix()
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl ser::SerializeTupleVariant for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        unimplemented!();
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<()> {
-        unimplemented!();
+        self.end_length_prefix()?;
+        self.end_variant()
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl ser::SerializeMap for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_key<T>(&mut self, _key: &T) -> Result<()>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        unimplemented!();
+        self.key_indexes.push(self.output.len());
+        key.serialize(MapKeySerializer { ser: self })
     }
 
-    fn serialize_value<T>(&mut self, _value: &T) -> Result<()>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        unimplemented!();
+        match self.key_indexes.pop() {
+            Some(key_index) => self.serialize_entry_value(key_index, value),
+            None => Err(Error::new(ErrorKind::Message(
+                "value serialized before its key".to_string(),
+            ))),
+        }
     }
 
     fn end(self) -> Result<()> {
-        unimplemented!();
+        self.end_map()
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl ser::SerializeStruct for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
@@ -312,33 +604,205 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        key.serialize(&mut **self)?;
-        value.serialize(&mut **self)
+        let key_index = self.output.len();
+        self.serialize_key(key)?;
+        self.serialize_entry_value(key_index, value)
+            .map_err(|err| err.with_key(key.as_bytes()))
     }
 
     fn end(self) -> Result<()> {
-        Ok(())
+        self.end_map()
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl ser::SerializeStructVariant for &mut Serializer {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, _key: &'static str, _value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        unimplemented!();
+        let key_index = self.output.len();
+        self.serialize_key(key)?;
+        self.serialize_entry_value(key_index, value)
+            .map_err(|err| err.with_key(key.as_bytes()))
     }
 
     fn end(self) -> Result<()> {
-        unimplemented!();
+        Serializer::end(self);
+        self.end_length_prefix()?;
+        self.end_variant()
+    }
+}
+
+fn key_must_be_a_string() -> Error {
+    Error::new(ErrorKind::KeyMustBeAString)
+}
+
+// Map keys become AMP keys, which are byte strings. Anything that isn't
+// string-like is rejected, rather than guessing at a string representation.
+struct MapKeySerializer<'a> {
+    ser: &'a mut Serializer,
+}
+
+impl<'a> ser::Serializer for MapKeySerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.ser.serialize_key(v)
+    }
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.ser.serialize_key(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.ser.serialize_key(variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_u16(self, _v: u16) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_u32(self, _v: u32) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_u64(self, _v: u64) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_i16(self, _v: i16) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_i32(self, _v: i32) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_i64(self, _v: i64) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_f64(self, _v: f64) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.ser.serialize_key_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_unit(self) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_must_be_a_string())
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::char_lit_as_u8, clippy::unnecessary_cast)]
 mod test {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+
     use super::*;
 
     #[test]
@@ -468,8 +932,9 @@ mod test {
         let expected = vec![
             0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
             2 as u8, '1' as u8, '0' as u8, 0 as u8, 6 as u8, 'n' as u8, 'e' as u8, 's' as u8,
-            't' as u8, 'e' as u8, 'd' as u8, 0 as u8, 5 as u8, 'i' as u8, 'n' as u8, 'n' as u8,
-            'e' as u8, 'r' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+            't' as u8, 'e' as u8, 'd' as u8, 0 as u8, 12 as u8, 0 as u8, 5 as u8, 'i' as u8,
+            'n' as u8, 'n' as u8, 'e' as u8, 'r' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8,
+            0 as u8, 0 as u8, 0 as u8,
         ];
 
         #[derive(Serialize)]
@@ -490,6 +955,34 @@ mod test {
         assert_eq!(expected, to_amp(&value).unwrap());
     }
 
+    #[test]
+    fn test_struct_nested_first() {
+        let expected = vec![
+            0 as u8, 6 as u8, 'n' as u8, 'e' as u8, 's' as u8, 't' as u8, 'e' as u8, 'd' as u8,
+            0 as u8, 12 as u8, 0 as u8, 5 as u8, 'i' as u8, 'n' as u8, 'n' as u8, 'e' as u8,
+            'r' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8, 5 as u8, 'v' as u8,
+            'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8,
+            0 as u8, 0 as u8,
+        ];
+
+        #[derive(Serialize)]
+        struct NestedStruct {
+            inner: usize,
+        }
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            nested: NestedStruct,
+            value: usize,
+        }
+
+        let value = TestStruct {
+            nested: NestedStruct { inner: 1 },
+            value: 10,
+        };
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
     #[test]
     fn test_sequence() {
         let expected = vec![
@@ -500,4 +993,435 @@ mod test {
         let value = vec![10, 11];
         assert_eq!(expected, to_amp(&value).unwrap());
     }
+
+    #[derive(Serialize)]
+    enum TestEnum {
+        Unit,
+        Newtype(u8),
+        Tuple(u8, u8),
+        Struct { x: u8 },
+    }
+
+    #[test]
+    fn test_unit_variant() {
+        let expected = vec![
+            0 as u8, 4 as u8, 'U' as u8, 'n' as u8, 'i' as u8, 't' as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&TestEnum::Unit).unwrap());
+    }
+
+    #[test]
+    fn test_newtype_variant() {
+        let expected = vec![
+            0 as u8, 7 as u8, 'N' as u8, 'e' as u8, 'w' as u8, 't' as u8, 'y' as u8, 'p' as u8,
+            'e' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&TestEnum::Newtype(1)).unwrap());
+    }
+
+    #[test]
+    fn test_tuple_variant() {
+        let expected = vec![
+            0 as u8, 5 as u8, 'T' as u8, 'u' as u8, 'p' as u8, 'l' as u8, 'e' as u8, 0 as u8,
+            6 as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&TestEnum::Tuple(1, 2)).unwrap());
+    }
+
+    #[test]
+    fn test_struct_variant() {
+        let expected = vec![
+            0 as u8, 6 as u8, 'S' as u8, 't' as u8, 'r' as u8, 'u' as u8, 'c' as u8, 't' as u8,
+            0 as u8, 8 as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8,
+            0 as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&TestEnum::Struct { x: 1 }).unwrap());
+    }
+
+    #[test]
+    fn test_nested_variant() {
+        let expected = vec![
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            14 as u8, 0 as u8, 7 as u8, 'N' as u8, 'e' as u8, 'w' as u8, 't' as u8, 'y' as u8,
+            'p' as u8, 'e' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8, 0 as u8, 0 as u8,
+        ];
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            value: TestEnum,
+        }
+
+        let value = TestStruct {
+            value: TestEnum::Newtype(1),
+        };
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_nested_unit_variant() {
+        let expected = vec![
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            4 as u8, 'U' as u8, 'n' as u8, 'i' as u8, 't' as u8, 0 as u8, 0 as u8,
+        ];
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            value: TestEnum,
+        }
+
+        let value = TestStruct {
+            value: TestEnum::Unit,
+        };
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_map() {
+        let expected = vec![
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'b' as u8,
+            0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+
+        let mut value = BTreeMap::new();
+        value.insert("a", 1);
+        value.insert("b", 2);
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_map_non_string_key() {
+        let mut value = BTreeMap::new();
+        value.insert(1, "a");
+        assert_eq!(
+            &ErrorKind::KeyMustBeAString,
+            to_amp(&value).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_flatten() {
+        let expected = vec![
+            0 as u8, 5 as u8, 'v' as u8, 'a' as u8, 'l' as u8, 'u' as u8, 'e' as u8, 0 as u8,
+            2 as u8, '1' as u8, '0' as u8, 0 as u8, 5 as u8, 'i' as u8, 'n' as u8, 'n' as u8,
+            'e' as u8, 'r' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 5 as u8, 'e' as u8,
+            'x' as u8, 't' as u8, 'r' as u8, 'a' as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8,
+            0 as u8,
+        ];
+
+        #[derive(Serialize)]
+        struct NestedStruct {
+            inner: usize,
+        }
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            value: usize,
+            #[serde(flatten)]
+            nested: NestedStruct,
+            #[serde(flatten)]
+            extra: BTreeMap<String, String>,
+        }
+
+        let mut extra = BTreeMap::new();
+        extra.insert("extra".to_string(), "x".to_string());
+        let value = TestStruct {
+            value: 10,
+            nested: NestedStruct { inner: 1 },
+            extra,
+        };
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_tuple() {
+        let expected = vec![
+            0 as u8, 7 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 1 as u8, 'a' as u8,
+            0 as u8, 0 as u8,
+        ];
+
+        let value = (10, "a");
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_tuple_struct() {
+        let expected = vec![
+            0 as u8, 8 as u8, 0 as u8, 2 as u8, '1' as u8, '0' as u8, 0 as u8, 2 as u8, '1' as u8,
+            '1' as u8, 0 as u8, 0 as u8,
+        ];
+
+        #[derive(Serialize)]
+        struct TestStruct(usize, usize);
+
+        let value = TestStruct(10, 11);
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_value_too_long() {
+        let value = "x".repeat(65536);
+        assert_eq!(&ErrorKind::ValueTooLong, to_amp(&value).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_sequence_too_long() {
+        let value = vec!["x".repeat(65533), "x".to_string()];
+        assert_eq!(&ErrorKind::ValueTooLong, to_amp(&value).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_unit() {
+        assert_eq!(
+            &ErrorKind::UnsupportedType("unit"),
+            to_amp(&()).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_key_too_long() {
+        let mut value = BTreeMap::new();
+        value.insert("x".repeat(255), "a");
+        assert!(to_amp(&value).is_ok());
+
+        value.insert("x".repeat(256), "a");
+        assert_eq!(&ErrorKind::KeyTooLong, to_amp(&value).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_empty_key() {
+        let mut value = BTreeMap::new();
+        value.insert("", "a");
+        assert_eq!(&ErrorKind::EmptyKey, to_amp(&value).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_value_longer_than_key() {
+        let mut value = BTreeMap::new();
+        value.insert("a", "x".repeat(65535));
+        assert!(to_amp(&value).is_ok());
+    }
+
+    #[test]
+    fn test_to_writer() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            value: usize,
+        }
+
+        let value = TestStruct { value: 10 };
+        let mut writer = vec![];
+        to_writer(&mut writer, &value).unwrap();
+        assert_eq!(to_amp(&value).unwrap(), writer);
+    }
+
+    #[test]
+    fn test_to_writer_not_a_box() {
+        let mut writer = vec![];
+        assert!(matches!(
+            to_writer(&mut writer, &5_u32).unwrap_err().kind(),
+            ErrorKind::UnsupportedType(_)
+        ));
+        assert!(matches!(
+            to_writer(&mut writer, &vec![1_u32, 2]).unwrap_err().kind(),
+            ErrorKind::UnsupportedType(_)
+        ));
+        assert!(writer.is_empty());
+    }
+
+    #[test]
+    fn test_option() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            a: Option<u8>,
+            b: Option<u8>,
+            c: u8,
+        }
+
+        let value = TestStruct {
+            a: None,
+            b: Some(1),
+            c: 2,
+        };
+        let expected = vec![
+            0 as u8, 1 as u8, 'b' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 1 as u8, 'c' as u8,
+            0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_option_map_value() {
+        let mut value = BTreeMap::new();
+        value.insert("a", None);
+        value.insert("b", Some("x"));
+        let expected = vec![
+            0 as u8, 1 as u8, 'b' as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_option_in_sequence() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            values: Vec<Option<u8>>,
+        }
+
+        let value = TestStruct {
+            values: vec![Some(1), None],
+        };
+        let error = to_amp(&value).unwrap_err();
+        assert_eq!(&ErrorKind::UnsupportedType("option"), error.kind());
+        assert_eq!(Some("values"), error.key());
+    }
+
+    #[test]
+    fn test_option_in_newtype_variant() {
+        #[derive(Serialize)]
+        enum TestEnum {
+            Newtype(Option<u8>),
+        }
+
+        let error = to_amp(&TestEnum::Newtype(None)).unwrap_err();
+        assert_eq!(&ErrorKind::UnsupportedType("option"), error.kind());
+        assert!(to_amp(&TestEnum::Newtype(Some(1))).is_ok());
+    }
+
+    #[test]
+    fn test_bytes() {
+        #[derive(Serialize)]
+        struct TestStruct<'a> {
+            #[serde(with = "serde_bytes")]
+            a: &'a [u8],
+        }
+
+        let value = TestStruct {
+            a: &[0xff, 0, 0xfe],
+        };
+        let expected = vec![
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 3 as u8, 0xff as u8, 0 as u8, 0xfe as u8,
+            0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_bytes_too_long() {
+        let mut value = BTreeMap::new();
+        value.insert("a", serde_bytes::ByteBuf::from(vec![0; 65536]));
+        assert_eq!(&ErrorKind::ValueTooLong, to_amp(&value).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_float_to_string() {
+        // As written by Python's `repr`, and so by Twisted's `amp.Float`.
+        let floats = [
+            (0.1, "0.1"),
+            (1e100, "1e+100"),
+            (-0.0, "-0.0"),
+            (0.0, "0.0"),
+            (1e16, "1e+16"),
+            (1e15, "1000000000000000.0"),
+            (1.5e-5, "1.5e-05"),
+            (0.0001, "0.0001"),
+            (-1e-7, "-1e-07"),
+            (123456789.123, "123456789.123"),
+            (100.0, "100.0"),
+            (1.23e-18, "1.23e-18"),
+            (5e-324, "5e-324"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (f64::NAN, "nan"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (value, expected) in floats {
+            assert_eq!(expected, float_to_string(value));
+        }
+    }
+
+    #[test]
+    fn test_serialize_f32_shortest() {
+        let number: f32 = 12.9;
+        let expected = vec![
+            0 as u8, 4 as u8, '1' as u8, '2' as u8, '.' as u8, '9' as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&number).unwrap());
+    }
+
+    #[test]
+    fn test_amp_list() {
+        #[derive(Serialize)]
+        struct Item {
+            a: u8,
+        }
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            items: Vec<Item>,
+        }
+
+        let value = TestStruct {
+            items: vec![Item { a: 1 }, Item { a: 2 }],
+        };
+        let expected = vec![
+            0 as u8, 5 as u8, 'i' as u8, 't' as u8, 'e' as u8, 'm' as u8, 's' as u8, 0 as u8,
+            16 as u8, 0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8, 0 as u8,
+            0 as u8, 1 as u8, 'a' as u8, 0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8, 0 as u8,
+            0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_amp_list_nested_struct() {
+        #[derive(Serialize)]
+        struct Inner {
+            x: u8,
+        }
+
+        #[derive(Serialize)]
+        struct Item {
+            inner: Inner,
+            y: u8,
+        }
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            items: Vec<Item>,
+            z: u8,
+        }
+
+        let value = TestStruct {
+            items: vec![Item {
+                inner: Inner { x: 1 },
+                y: 2,
+            }],
+            z: 3,
+        };
+        let expected = vec![
+            0 as u8, 5 as u8, 'i' as u8, 't' as u8, 'e' as u8, 'm' as u8, 's' as u8, 0 as u8,
+            25 as u8, 0 as u8, 5 as u8, 'i' as u8, 'n' as u8, 'n' as u8, 'e' as u8, 'r' as u8,
+            0 as u8, 8 as u8, 0 as u8, 1 as u8, 'x' as u8, 0 as u8, 1 as u8, '1' as u8, 0 as u8,
+            0 as u8, 0 as u8, 1 as u8, 'y' as u8, 0 as u8, 1 as u8, '2' as u8, 0 as u8, 0 as u8,
+            0 as u8, 1 as u8, 'z' as u8, 0 as u8, 1 as u8, '3' as u8, 0 as u8, 0 as u8,
+        ];
+        assert_eq!(expected, to_amp(&value).unwrap());
+    }
+
+    #[test]
+    fn test_amp_list_too_long() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            items: Vec<BTreeMap<&'static str, String>>,
+        }
+
+        let mut item = BTreeMap::new();
+        item.insert("a", "x".repeat(40000));
+        let value = TestStruct {
+            items: vec![item.clone(), item],
+        };
+        let error = to_amp(&value).unwrap_err();
+        assert_eq!(&ErrorKind::ValueTooLong, error.kind());
+        assert_eq!(Some("items"), error.key());
+    }
 }
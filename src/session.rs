@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+
+use crate::amp_box::AmpBox;
+use crate::command::{Command, CommandError};
+use crate::de::{from_bytes, read_next_box};
+use crate::envelope::{AnswerBox, CommandBox, ErrorBox};
+use crate::error::{Error, ErrorKind, Result};
+
+// Twisted's error codes for a command that isn't known, and for an error that
+// the command doesn't declare.
+pub const UNKNOWN_ERROR_CODE: &str = "UNKNOWN";
+pub const UNHANDLED_ERROR_CODE: &str = "UNHANDLED";
+
+// One end of an AMP connection, without the connection itself. Bytes read
+// from the transport are handed to `receive_data`, and come back out of
+// `next_event` as whole boxes; boxes to be sent pile up until they are taken
+// with `data_to_send`.
+//
+// Commands are tagged with `_ask` as they are called, and the answers to
+// them are matched back up by their `_answer` or `_error` tag. An answer to
+// a tag that was never asked, or that has already been answered, is a
+// protocol error.
+#[derive(Debug, Default)]
+pub struct Session {
+    // The last `_ask` tag handed out. As in Twisted, tags count up from 1 in
+    // hex, so any tag up to this one has been asked.
+    last_tag: u64,
+
+    // The name of the command asked with each tag that is still waiting for
+    // its answer.
+    pending: HashMap<String, &'static str>,
+
+    input: Vec<u8>,
+
+    // Where `input` starts in the stream of bytes received, to report errors
+    // against.
+    offset: usize,
+
+    output: Vec<u8>,
+}
+
+// Something that happened on the connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // The other end called a command, which should be answered with
+    // `Session::answer` or one of the `answer_error` methods, if it has an
+    // `_ask` tag.
+    Command(IncomingCommand),
+
+    // A command this end called was answered.
+    Answer(Answer),
+
+    // A command this end called failed.
+    Error(ErrorAnswer),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IncomingCommand {
+    pub command: String,
+    pub ask: Option<String>,
+    amp_box: Vec<u8>,
+}
+
+impl IncomingCommand {
+    // Decodes the command's arguments, once `command` says which command it
+    // is. Fails with `ErrorKind::BadData` if `C` is any other command.
+    pub fn arguments<C>(&self) -> Result<C::Arguments>
+    where
+        C: Command,
+    {
+        CommandBox::from_bytes_for::<C>(&self.amp_box).map(|amp_box| amp_box.arguments)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Answer {
+    pub ask: String,
+
+    // The name of the command that was asked.
+    pub command: &'static str,
+    amp_box: Vec<u8>,
+}
+
+impl Answer {
+    // Decodes the response, which fails with `ErrorKind::BadData` if `C`
+    // isn't the command that was asked.
+    pub fn response<C>(&self) -> Result<C::Response>
+    where
+        C: Command,
+    {
+        if self.command != C::NAME {
+            return Err(Error::new(ErrorKind::BadData).with_value(C::NAME, self.command.as_bytes()));
+        }
+        AnswerBox::from_bytes(&self.amp_box).map(|amp_box| amp_box.response)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorAnswer {
+    // The name of the command that was asked.
+    pub command: &'static str,
+    pub error: ErrorBox,
+}
+
+impl ErrorAnswer {
+    // The error as one of the command's declared errors, or `None` when its
+    // code isn't one of them, such as for Twisted's `UNKNOWN` and `UNHANDLED`.
+    pub fn declared<C>(&self) -> Option<C::Error>
+    where
+        C: Command,
+    {
+        C::Error::from_code(&self.error.code, &self.error.description)
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Queues a command to be sent, returning the `_ask` tag its answer will
+    // come back with, or `None` when it doesn't require an answer.
+    pub fn call<C>(&mut self, arguments: &C::Arguments) -> Result<Option<String>>
+    where
+        C: Command,
+    {
+        let ask = if C::REQUIRES_ANSWER {
+            Some(format!("{:x}", self.last_tag + 1))
+        } else {
+            None
+        };
+        let amp_box = CommandBox {
+            command: C::NAME.to_string(),
+            ask: ask.clone(),
+            arguments,
+        };
+        self.output.extend(amp_box.to_amp()?);
+        if let Some(ask) = &ask {
+            self.last_tag += 1;
+            self.pending.insert(ask.clone(), C::NAME);
+        }
+        Ok(ask)
+    }
+
+    // Queues the response to a command the other end called.
+    pub fn answer<C>(&mut self, ask: &str, response: &C::Response) -> Result<()>
+    where
+        C: Command,
+    {
+        let amp_box = AnswerBox {
+            answer: ask.to_string(),
+            response,
+        };
+        self.output.extend(amp_box.to_amp()?);
+        Ok(())
+    }
+
+    // Queues one of the command's declared errors as the answer to a command
+    // the other end called. If the error is fatal, the connection should be
+    // closed once it's sent.
+    pub fn answer_error<C>(&mut self, ask: &str, error: &C::Error) -> Result<()>
+    where
+        C: Command,
+    {
+        self.answer_error_code(ask, error.code(), &error.to_string())
+    }
+
+    // Queues an error with any code, such as `UNKNOWN_ERROR_CODE` for a
+    // command that isn't known, or `UNHANDLED_ERROR_CODE` for an error the
+    // command doesn't declare.
+    pub fn answer_error_code(&mut self, ask: &str, code: &str, description: &str) -> Result<()> {
+        let amp_box = ErrorBox {
+            error: ask.to_string(),
+            code: code.to_string(),
+            description: description.to_string(),
+        };
+        self.output.extend(amp_box.to_amp()?);
+        Ok(())
+    }
+
+    // The number of commands called that are still waiting for an answer.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn receive_data(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    // The next whole box received, or `None` until more data arrives. A box
+    // which can't be made sense of is consumed and its error returned, so
+    // the session carries on with the next box. If the boxes themselves
+    // can't be told apart, the connection is beyond saving, and the same
+    // error is returned until it is dropped.
+    pub fn next_event(&mut self) -> Result<Option<Event>> {
+        let amp_box = match read_next_box(&mut &self.input[..], self.offset) {
+            Ok(Some(amp_box)) => amp_box,
+            Ok(None) => return Ok(None),
+            Err(err) if err.kind() == &ErrorKind::Eof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let offset = self.offset;
+        self.input.drain(..amp_box.len());
+        self.offset += amp_box.len();
+        self.event(amp_box)
+            .map(Some)
+            .map_err(|err| err.with_offset(offset))
+    }
+
+    // Takes everything queued to be sent.
+    pub fn data_to_send(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    // As in Twisted, a box that is neither an answer nor an error is a
+    // command.
+    fn event(&mut self, amp_box: Vec<u8>) -> Result<Event> {
+        let keys: AmpBox = from_bytes(&amp_box)?;
+        if let Some(ask) = keys.get_str("_answer")? {
+            let command = self.answered("_answer", ask)?;
+            return Ok(Event::Answer(Answer {
+                ask: ask.to_string(),
+                command,
+                amp_box,
+            }));
+        }
+        // The error is decoded before its tag is settled, so that a malformed
+        // error leaves the command waiting for a proper answer.
+        if let Some(ask) = keys.get_str("_error")? {
+            let error = ErrorBox::from_bytes(&amp_box)?;
+            let command = self.answered("_error", ask)?;
+            return Ok(Event::Error(ErrorAnswer { command, error }));
+        }
+        match keys.get_str("_command")? {
+            Some(command) => Ok(Event::Command(IncomingCommand {
+                command: command.to_string(),
+                ask: keys.get_str("_ask")?.map(str::to_string),
+                amp_box,
+            })),
+            None => Err(Error::new(ErrorKind::MissingKey("_command"))),
+        }
+    }
+
+    // Settles the command asked with `ask`, returning its name.
+    fn answered(&mut self, key: &str, ask: &str) -> Result<&'static str> {
+        if let Some(command) = self.pending.remove(ask) {
+            return Ok(command);
+        }
+        let asked = match u64::from_str_radix(ask, 16) {
+            Ok(tag) => (1..=self.last_tag).contains(&tag) && *ask == format!("{:x}", tag),
+            Err(_) => false,
+        };
+        let kind = if asked {
+            ErrorKind::DuplicateAnswer
+        } else {
+            ErrorKind::UnknownAnswer
+        };
+        Err(Error::new(kind)
+            .with_value("an outstanding _ask tag", ask.as_bytes())
+            .with_key(key.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::fmt;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct SumArguments {
+        a: i64,
+        b: i64,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct SumResponse {
+        total: i64,
+    }
+
+    struct Sum;
+
+    impl Command for Sum {
+        const NAME: &'static str = "Sum";
+        type Arguments = SumArguments;
+        type Response = SumResponse;
+        type Error = SumError;
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct SumError;
+
+    impl fmt::Display for SumError {
+        fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("overflow")
+        }
+    }
+
+    impl CommandError for SumError {
+        fn code(&self) -> &str {
+            "OVERFLOW"
+        }
+
+        fn from_code(code: &str, _description: &str) -> Option<Self> {
+            match code {
+                "OVERFLOW" => Some(SumError),
+                _ => None,
+            }
+        }
+    }
+
+    struct Notify;
+
+    impl Command for Notify {
+        const NAME: &'static str = "Notify";
+        const REQUIRES_ANSWER: bool = false;
+        type Arguments = SumArguments;
+        type Response = SumResponse;
+        type Error = Infallible;
+    }
+
+    // Passes everything one session has to send to the other.
+    fn transfer(from: &mut Session, to: &mut Session) {
+        to.receive_data(&from.data_to_send());
+    }
+
+    #[test]
+    fn test_call_and_answer() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        let ask = client.call::<Sum>(&SumArguments { a: 13, b: 81 }).unwrap();
+        assert_eq!(Some("1".to_string()), ask);
+        assert_eq!(1, client.pending());
+        transfer(&mut client, &mut server);
+
+        let command = match server.next_event().unwrap() {
+            Some(Event::Command(command)) => command,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!("Sum", command.command);
+        let arguments = command.arguments::<Sum>().unwrap();
+        assert_eq!(SumArguments { a: 13, b: 81 }, arguments);
+        let response = SumResponse {
+            total: arguments.a + arguments.b,
+        };
+        server
+            .answer::<Sum>(command.ask.as_deref().unwrap(), &response)
+            .unwrap();
+        assert_eq!(None, server.next_event().unwrap());
+        transfer(&mut server, &mut client);
+
+        let answer = match client.next_event().unwrap() {
+            Some(Event::Answer(answer)) => answer,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!("1", answer.ask);
+        assert_eq!("Sum", answer.command);
+        assert_eq!(response, answer.response::<Sum>().unwrap());
+        assert_eq!(0, client.pending());
+    }
+
+    #[test]
+    fn test_wrong_command() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        client.call::<Sum>(&SumArguments { a: 1, b: 2 }).unwrap();
+        transfer(&mut client, &mut server);
+
+        let command = match server.next_event().unwrap() {
+            Some(Event::Command(command)) => command,
+            event => panic!("unexpected event {:?}", event),
+        };
+        let error = command.arguments::<Notify>().unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some("Notify"), error.expected());
+        assert_eq!(Some(&b"Sum"[..]), error.value());
+
+        server
+            .answer::<Sum>(command.ask.as_deref().unwrap(), &SumResponse { total: 3 })
+            .unwrap();
+        transfer(&mut server, &mut client);
+        let answer = match client.next_event().unwrap() {
+            Some(Event::Answer(answer)) => answer,
+            event => panic!("unexpected event {:?}", event),
+        };
+        let error = answer.response::<Notify>().unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some("Notify"), error.expected());
+        assert_eq!(Some(&b"Sum"[..]), error.value());
+    }
+
+    #[test]
+    fn test_answers_out_of_order() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        for a in 0..17 {
+            client.call::<Sum>(&SumArguments { a, b: 1 }).unwrap();
+        }
+        transfer(&mut client, &mut server);
+
+        let mut commands = vec![];
+        while let Some(Event::Command(command)) = server.next_event().unwrap() {
+            commands.push(command);
+        }
+        assert_eq!(Some("11"), commands[16].ask.as_deref());
+        for command in commands.iter().rev() {
+            let arguments = command.arguments::<Sum>().unwrap();
+            let response = SumResponse {
+                total: arguments.a + arguments.b,
+            };
+            server
+                .answer::<Sum>(command.ask.as_deref().unwrap(), &response)
+                .unwrap();
+        }
+        transfer(&mut server, &mut client);
+
+        let mut totals = vec![];
+        while let Some(Event::Answer(answer)) = client.next_event().unwrap() {
+            totals.push(answer.response::<Sum>().unwrap().total);
+        }
+        assert_eq!((1..18).rev().collect::<Vec<_>>(), totals);
+        assert_eq!(0, client.pending());
+    }
+
+    #[test]
+    fn test_no_answer_required() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        let ask = client.call::<Notify>(&SumArguments { a: 1, b: 2 }).unwrap();
+        assert_eq!(None, ask);
+        assert_eq!(0, client.pending());
+        transfer(&mut client, &mut server);
+
+        match server.next_event().unwrap() {
+            Some(Event::Command(command)) => assert_eq!(None, command.ask),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(
+            Some("1".to_string()),
+            client.call::<Sum>(&SumArguments { a: 1, b: 2 }).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_error_answer() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        client.call::<Sum>(&SumArguments { a: 1, b: 2 }).unwrap();
+        client.call::<Sum>(&SumArguments { a: 3, b: 4 }).unwrap();
+        server.answer_error::<Sum>("1", &SumError).unwrap();
+        server
+            .answer_error_code("2", UNHANDLED_ERROR_CODE, "Unhandled Error")
+            .unwrap();
+        transfer(&mut server, &mut client);
+
+        let error = match client.next_event().unwrap() {
+            Some(Event::Error(error)) => error,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!("Sum", error.command);
+        assert_eq!("overflow", error.error.description);
+        assert_eq!(Some(SumError), error.declared::<Sum>());
+
+        let error = match client.next_event().unwrap() {
+            Some(Event::Error(error)) => error,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!("UNHANDLED", error.error.code);
+        assert_eq!(None, error.declared::<Sum>());
+        assert_eq!(0, client.pending());
+    }
+
+    #[test]
+    fn test_partial_data() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        client.call::<Sum>(&SumArguments { a: 1, b: 2 }).unwrap();
+        let bytes = client.data_to_send();
+        for byte in &bytes[..bytes.len() - 1] {
+            server.receive_data(&[*byte]);
+            assert_eq!(None, server.next_event().unwrap());
+        }
+        server.receive_data(&bytes[bytes.len() - 1..]);
+        assert!(matches!(
+            server.next_event().unwrap(),
+            Some(Event::Command(_))
+        ));
+        assert_eq!(None, server.next_event().unwrap());
+    }
+
+    #[test]
+    fn test_unknown_answer() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        client.call::<Sum>(&SumArguments { a: 1, b: 2 }).unwrap();
+        server
+            .answer::<Sum>("2", &SumResponse { total: 3 })
+            .unwrap();
+        server
+            .answer::<Sum>("1", &SumResponse { total: 3 })
+            .unwrap();
+        transfer(&mut server, &mut client);
+
+        let error = client.next_event().unwrap_err();
+        assert_eq!(&ErrorKind::UnknownAnswer, error.kind());
+        assert_eq!(Some("_answer"), error.key());
+        assert_eq!(Some(&b"2"[..]), error.value());
+        assert_eq!(Some(0), error.offset());
+        assert!(matches!(
+            client.next_event().unwrap(),
+            Some(Event::Answer(_))
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_answer() {
+        let mut client = Session::new();
+        let mut server = Session::new();
+        client.call::<Sum>(&SumArguments { a: 1, b: 2 }).unwrap();
+        server
+            .answer::<Sum>("1", &SumResponse { total: 3 })
+            .unwrap();
+        server.answer_error::<Sum>("1", &SumError).unwrap();
+        transfer(&mut server, &mut client);
+
+        assert!(matches!(
+            client.next_event().unwrap(),
+            Some(Event::Answer(_))
+        ));
+        let error = client.next_event().unwrap_err();
+        assert_eq!(&ErrorKind::DuplicateAnswer, error.kind());
+        assert_eq!(Some("_error"), error.key());
+        assert_eq!(None, client.next_event().unwrap());
+    }
+
+    #[test]
+    fn test_malformed_error_answer() {
+        let mut client = Session::new();
+        client.call::<Sum>(&SumArguments { a: 1, b: 2 }).unwrap();
+        let mut amp_box = AmpBox::new();
+        amp_box.append("_error", "1");
+        amp_box.append("_error_description", "overflow");
+        client.receive_data(&crate::to_amp(&amp_box).unwrap());
+
+        let error = client.next_event().unwrap_err();
+        assert_eq!(&ErrorKind::MissingKey("_error_code"), error.kind());
+        assert_eq!(1, client.pending());
+
+        let mut server = Session::new();
+        server.answer_error::<Sum>("1", &SumError).unwrap();
+        transfer(&mut server, &mut client);
+        match client.next_event().unwrap() {
+            Some(Event::Error(error)) => assert_eq!("OVERFLOW", error.error.code),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(0, client.pending());
+    }
+
+    #[test]
+    fn test_not_a_command() {
+        let mut session = Session::new();
+        let mut amp_box = AmpBox::new();
+        amp_box.append("a", "1");
+        session.receive_data(&crate::to_amp(&amp_box).unwrap());
+        let error = session.next_event().unwrap_err();
+        assert_eq!(&ErrorKind::MissingKey("_command"), error.kind());
+    }
+
+    #[test]
+    fn test_malformed_data() {
+        let mut session = Session::new();
+        session.receive_data(&[1, 0]);
+        let error = session.next_event().unwrap_err();
+        assert_eq!(&ErrorKind::KeyTooLong, error.kind());
+        assert!(session.next_event().is_err());
+    }
+}
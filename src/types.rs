@@ -0,0 +1,1025 @@
+use std::fmt;
+use std::ops::Deref;
+use std::path::{self, PathBuf};
+use std::str::{self, FromStr};
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+
+use crate::error::{Error, ErrorKind, Result};
+
+// Twisted's `amp.String`: a byte string, kept as it is whether or not it is
+// valid UTF-8.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        Bytes(bytes.to_vec())
+    }
+}
+
+impl From<Bytes> for Vec<u8> {
+    fn from(bytes: Bytes) -> Self {
+        bytes.0
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte string")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Bytes, E>
+    where
+        E: de::Error,
+    {
+        Ok(Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Bytes, E>
+    where
+        E: de::Error,
+    {
+        Ok(Bytes(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Bytes, E>
+    where
+        E: de::Error,
+    {
+        Ok(Bytes(v.as_bytes().to_vec()))
+    }
+}
+
+// Twisted's `amp.Unicode`: text, which is encoded as UTF-8 and must decode
+// as UTF-8.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Unicode(pub String);
+
+impl Deref for Unicode {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Unicode {
+    fn from(string: String) -> Self {
+        Unicode(string)
+    }
+}
+
+impl From<&str> for Unicode {
+    fn from(string: &str) -> Self {
+        Unicode(string.to_string())
+    }
+}
+
+impl From<Unicode> for String {
+    fn from(unicode: Unicode) -> Self {
+        unicode.0
+    }
+}
+
+impl fmt::Display for Unicode {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+impl Serialize for Unicode {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Unicode {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_string(UnicodeVisitor)
+    }
+}
+
+struct UnicodeVisitor;
+
+impl<'de> Visitor<'de> for UnicodeVisitor {
+    type Value = Unicode;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a UTF-8 string")
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Unicode, E>
+    where
+        E: de::Error,
+    {
+        Ok(Unicode(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Unicode, E>
+    where
+        E: de::Error,
+    {
+        Ok(Unicode(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Unicode, E>
+    where
+        E: de::Error,
+    {
+        match str::from_utf8(v) {
+            Ok(string) => self.visit_str(string),
+            Err(_) => Err(E::invalid_value(de::Unexpected::Bytes(v), &self)),
+        }
+    }
+}
+
+// Twisted's `amp.Path`: a `FilePath`, written as its path encoded as UTF-8.
+// A path which isn't valid UTF-8, as paths on Unix needn't be, can't be
+// written.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Path(pub PathBuf);
+
+impl Deref for Path {
+    type Target = path::Path;
+
+    fn deref(&self) -> &path::Path {
+        &self.0
+    }
+}
+
+impl From<PathBuf> for Path {
+    fn from(path: PathBuf) -> Self {
+        Path(path)
+    }
+}
+
+impl From<&path::Path> for Path {
+    fn from(path: &path::Path) -> Self {
+        Path(path.to_path_buf())
+    }
+}
+
+impl From<Path> for PathBuf {
+    fn from(path: Path) -> Self {
+        path.0
+    }
+}
+
+impl Serialize for Path {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0.to_str() {
+            Some(path) => serializer.serialize_str(path),
+            None => Err(ser::Error::custom(format!(
+                "Path {:?} is not valid UTF-8",
+                self.0
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Path {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_string(UnicodeVisitor)
+            .map(|unicode| Path(PathBuf::from(unicode.0)))
+    }
+}
+
+// Twisted's `amp.Decimal`, which is Python's `decimal.Decimal` written with
+// `str`. Values are kept exactly as they are written, with arbitrary
+// precision, so nothing is lost going through `f64`. As with Python's
+// `compare_total`, values are only equal when they are written the same,
+// so `1.10` and `1.1` are different.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Decimal {
+    negative: bool,
+    value: DecimalValue,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum DecimalValue {
+    // The value is `coefficient * 10^exponent`. The coefficient is a string
+    // of digits without leading zeros, so precision isn't limited.
+    Finite { coefficient: String, exponent: i64 },
+    Infinity,
+    // NaNs carry a diagnostic payload of digits, often empty.
+    NaN(String),
+    SignalingNaN(String),
+}
+
+impl Decimal {
+    pub fn is_nan(&self) -> bool {
+        matches!(
+            self.value,
+            DecimalValue::NaN(_) | DecimalValue::SignalingNaN(_)
+        )
+    }
+
+    pub fn is_signaling_nan(&self) -> bool {
+        matches!(self.value, DecimalValue::SignalingNaN(_))
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        self.value == DecimalValue::Infinity
+    }
+
+    pub fn is_finite(&self) -> bool {
+        matches!(self.value, DecimalValue::Finite { .. })
+    }
+
+    pub fn is_sign_negative(&self) -> bool {
+        self.negative
+    }
+}
+
+fn is_digits(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn strip_zeros(digits: &str) -> &str {
+    digits.trim_start_matches('0')
+}
+
+// Parses anything Python's `decimal.Decimal` would, save for the whitespace
+// and underscores that Python is lenient about.
+fn parse_decimal(s: &str) -> Option<Decimal> {
+    let (negative, rest) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+
+    let lower = rest.to_ascii_lowercase();
+    let value = if lower == "inf" || lower == "infinity" {
+        DecimalValue::Infinity
+    } else if let Some(payload) = lower.strip_prefix("snan") {
+        if !is_digits(payload) {
+            return None;
+        }
+        DecimalValue::SignalingNaN(strip_zeros(payload).to_string())
+    } else if let Some(payload) = lower.strip_prefix("nan") {
+        if !is_digits(payload) {
+            return None;
+        }
+        DecimalValue::NaN(strip_zeros(payload).to_string())
+    } else {
+        let (mantissa, exponent) = match rest.find(['e', 'E']) {
+            Some(index) => (&rest[..index], rest[index + 1..].parse::<i64>().ok()?),
+            None => (rest, 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if integer.is_empty() && fraction.is_empty() || !is_digits(integer) || !is_digits(fraction)
+        {
+            return None;
+        }
+
+        let exponent = exponent.checked_sub(fraction.len() as i64)?;
+        let coefficient = strip_zeros(&format!("{}{}", integer, fraction)).to_string();
+        let coefficient = if coefficient.is_empty() {
+            "0".to_string()
+        } else {
+            coefficient
+        };
+        DecimalValue::Finite {
+            coefficient,
+            exponent,
+        }
+    };
+    Some(Decimal { negative, value })
+}
+
+impl FromStr for Decimal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_decimal(s)
+            .ok_or_else(|| Error::new(ErrorKind::BadData).with_value("a decimal", s.as_bytes()))
+    }
+}
+
+// Python's `str`, which is the "to-scientific-string" conversion of the
+// General Decimal Arithmetic specification: plain notation unless the
+// exponent is positive, or the value is very small.
+impl fmt::Display for Decimal {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        if self.negative {
+            formatter.write_str("-")?;
+        }
+        let (coefficient, exponent) = match &self.value {
+            DecimalValue::Finite {
+                coefficient,
+                exponent,
+            } => (coefficient, *exponent),
+            DecimalValue::Infinity => return formatter.write_str("Infinity"),
+            DecimalValue::NaN(payload) => return write!(formatter, "NaN{}", payload),
+            DecimalValue::SignalingNaN(payload) => return write!(formatter, "sNaN{}", payload),
+        };
+
+        let length = coefficient.len() as i64;
+        let adjusted = exponent.saturating_add(length - 1);
+        if exponent <= 0 && adjusted >= -6 {
+            let point = length + exponent;
+            if exponent == 0 {
+                formatter.write_str(coefficient)
+            } else if point > 0 {
+                let (integer, fraction) = coefficient.split_at(point as usize);
+                write!(formatter, "{}.{}", integer, fraction)
+            } else {
+                let zeros = "0".repeat(-point as usize);
+                write!(formatter, "0.{}{}", zeros, coefficient)
+            }
+        } else {
+            let (first, rest) = coefficient.split_at(1);
+            formatter.write_str(first)?;
+            if !rest.is_empty() {
+                write!(formatter, ".{}", rest)?;
+            }
+            let sign = if adjusted < 0 { '-' } else { '+' };
+            write!(formatter, "E{}{}", sign, adjusted.unsigned_abs())
+        }
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(DecimalVisitor)
+    }
+}
+
+struct DecimalVisitor;
+
+impl<'de> Visitor<'de> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal")
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Decimal, E>
+    where
+        E: de::Error,
+    {
+        parse_decimal(v).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl From<rust_decimal::Decimal> for Decimal {
+    fn from(decimal: rust_decimal::Decimal) -> Self {
+        let coefficient = decimal.mantissa().unsigned_abs().to_string();
+        Decimal {
+            negative: decimal.is_sign_negative(),
+            value: DecimalValue::Finite {
+                coefficient,
+                exponent: -(decimal.scale() as i64),
+            },
+        }
+    }
+}
+
+// Only finite values which fit in `rust_decimal`'s 96 bits, with at most 28
+// decimal places, can be converted exactly.
+#[cfg(feature = "rust_decimal")]
+impl TryFrom<Decimal> for rust_decimal::Decimal {
+    type Error = Error;
+
+    fn try_from(decimal: Decimal) -> Result<Self> {
+        let out_of_range = || {
+            Error::new(ErrorKind::BadData)
+                .with_value("rust_decimal::Decimal", decimal.to_string().as_bytes())
+        };
+        let (coefficient, exponent) = match &decimal.value {
+            DecimalValue::Finite {
+                coefficient,
+                exponent,
+            } => (coefficient, *exponent),
+            _ => return Err(out_of_range()),
+        };
+
+        // Nothing with more than 29 digits fits in 96 bits.
+        let (digits, scale) = if exponent > 28 {
+            return Err(out_of_range());
+        } else if exponent > 0 {
+            (
+                format!("{}{}", coefficient, "0".repeat(exponent as usize)),
+                0,
+            )
+        } else {
+            let scale = u32::try_from(-exponent).map_err(|_| out_of_range())?;
+            (coefficient.clone(), scale)
+        };
+        let mantissa: i128 = digits.parse().map_err(|_| out_of_range())?;
+        let mut result = rust_decimal::Decimal::try_from_i128_with_scale(mantissa, scale)
+            .map_err(|_| out_of_range())?;
+        result.set_sign_negative(decimal.negative);
+        Ok(result)
+    }
+}
+
+// Twisted's `amp.DateTime`: a timestamp with microseconds and an offset from
+// UTC in minutes, written `YYYY-MM-DDTHH:MM:SS.ffffff+HH:MM`. As in Twisted,
+// there is no naive form without an offset, and years run from 1 to 9999.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    microsecond: u32,
+
+    // Minutes east of UTC.
+    offset: i16,
+}
+
+impl DateTime {
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    pub fn microsecond(&self) -> u32 {
+        self.microsecond
+    }
+
+    // The offset from UTC, in minutes east.
+    pub fn offset_minutes(&self) -> i16 {
+        self.offset
+    }
+
+    // Python's `datetime` has no leap seconds, and its offsets are less than
+    // a day either way.
+    #[allow(clippy::manual_is_multiple_of)]
+    fn is_valid(&self) -> bool {
+        let leap_year = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+        let days = match self.month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        (1..=9999).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=days).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+            && self.microsecond < 1_000_000
+            && self.offset.abs() < 24 * 60
+    }
+}
+
+// Reads a field of fixed width, which must be all digits.
+fn parse_digits(bytes: &[u8]) -> Option<u32> {
+    if !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, digit| value * 10 + (digit - b'0') as u32),
+    )
+}
+
+fn parse_date_time(s: &str) -> Option<DateTime> {
+    let bytes = s.as_bytes();
+    if bytes.len() != 32 {
+        return None;
+    }
+    for (index, separator) in [
+        (4, b'-'),
+        (7, b'-'),
+        (10, b'T'),
+        (13, b':'),
+        (16, b':'),
+        (19, b'.'),
+        (29, b':'),
+    ] {
+        if bytes[index] != separator {
+            return None;
+        }
+    }
+    let sign = match bytes[26] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+
+    let offset_minutes = parse_digits(&bytes[30..32])?;
+    if offset_minutes >= 60 {
+        return None;
+    }
+    let offset = parse_digits(&bytes[27..29])? * 60 + offset_minutes;
+    let date_time = DateTime {
+        year: parse_digits(&bytes[0..4])? as u16,
+        month: parse_digits(&bytes[5..7])? as u8,
+        day: parse_digits(&bytes[8..10])? as u8,
+        hour: parse_digits(&bytes[11..13])? as u8,
+        minute: parse_digits(&bytes[14..16])? as u8,
+        second: parse_digits(&bytes[17..19])? as u8,
+        microsecond: parse_digits(&bytes[20..26])?,
+        offset: sign * offset as i16,
+    };
+    if date_time.is_valid() {
+        Some(date_time)
+    } else {
+        None
+    }
+}
+
+impl FromStr for DateTime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_date_time(s)
+            .ok_or_else(|| Error::new(ErrorKind::BadData).with_value("a datetime", s.as_bytes()))
+    }
+}
+
+// Twisted only writes a `+` for offsets east of UTC, so UTC itself is
+// written as `-00:00`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.offset > 0 { '+' } else { '-' };
+        let offset = self.offset.unsigned_abs();
+        write!(
+            formatter,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}{}{:02}:{:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.microsecond,
+            sign,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+impl Serialize for DateTime {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DateTime {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(DateTimeVisitor)
+    }
+}
+
+struct DateTimeVisitor;
+
+impl<'de> Visitor<'de> for DateTimeVisitor {
+    type Value = DateTime;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a datetime formatted as YYYY-MM-DDTHH:MM:SS.ffffff+HH:MM")
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<DateTime, E>
+    where
+        E: de::Error,
+    {
+        parse_date_time(v).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+// Converting from another crate's datetime fails when it can't be written
+// by Twisted: years outside of 1 to 9999, leap seconds, or offsets that
+// aren't whole minutes. Anything finer than microseconds is truncated.
+#[cfg(any(feature = "chrono", feature = "time"))]
+#[allow(clippy::too_many_arguments)]
+fn date_time_from_parts(
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanosecond: u32,
+    offset_seconds: i32,
+) -> Result<DateTime> {
+    let date_time = DateTime {
+        year: u16::try_from(year).unwrap_or(0),
+        month: month as u8,
+        day: day as u8,
+        hour: hour as u8,
+        minute: minute as u8,
+        second: second as u8,
+        microsecond: nanosecond / 1000,
+        offset: (offset_seconds / 60) as i16,
+    };
+    if date_time.is_valid() && offset_seconds % 60 == 0 {
+        Ok(date_time)
+    } else {
+        let value = format!(
+            "{}-{}-{} {}:{}:{}.{:09} {:+}s",
+            year, month, day, hour, minute, second, nanosecond, offset_seconds
+        );
+        Err(Error::new(ErrorKind::BadData).with_value("types::DateTime", value.as_bytes()))
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz> TryFrom<chrono::DateTime<Tz>> for DateTime
+where
+    Tz: chrono::TimeZone,
+{
+    type Error = Error;
+
+    fn try_from(date_time: chrono::DateTime<Tz>) -> Result<Self> {
+        use chrono::{Datelike, Offset, Timelike};
+
+        let local = date_time.naive_local();
+        date_time_from_parts(
+            local.year(),
+            local.month(),
+            local.day(),
+            local.hour(),
+            local.minute(),
+            local.second(),
+            local.nanosecond(),
+            date_time.offset().fix().local_minus_utc(),
+        )
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<DateTime> for chrono::DateTime<chrono::FixedOffset> {
+    type Error = Error;
+
+    fn try_from(date_time: DateTime) -> Result<Self> {
+        let offset = chrono::FixedOffset::east_opt(date_time.offset as i32 * 60);
+        let local = chrono::NaiveDate::from_ymd_opt(
+            date_time.year as i32,
+            date_time.month as u32,
+            date_time.day as u32,
+        )
+        .and_then(|date| {
+            date.and_hms_micro_opt(
+                date_time.hour as u32,
+                date_time.minute as u32,
+                date_time.second as u32,
+                date_time.microsecond,
+            )
+        });
+        match (local, offset) {
+            (Some(local), Some(offset)) => local
+                .and_local_timezone(offset)
+                .single()
+                .ok_or_else(|| out_of_range(date_time)),
+            _ => Err(out_of_range(date_time)),
+        }
+    }
+}
+
+#[cfg(feature = "time")]
+impl TryFrom<time::OffsetDateTime> for DateTime {
+    type Error = Error;
+
+    fn try_from(date_time: time::OffsetDateTime) -> Result<Self> {
+        date_time_from_parts(
+            date_time.year(),
+            date_time.month() as u32,
+            date_time.day() as u32,
+            date_time.hour() as u32,
+            date_time.minute() as u32,
+            date_time.second() as u32,
+            date_time.nanosecond(),
+            date_time.offset().whole_seconds(),
+        )
+    }
+}
+
+#[cfg(feature = "time")]
+impl TryFrom<DateTime> for time::OffsetDateTime {
+    type Error = Error;
+
+    fn try_from(date_time: DateTime) -> Result<Self> {
+        let month = time::Month::try_from(date_time.month).map_err(|_| out_of_range(date_time))?;
+        let date = time::Date::from_calendar_date(date_time.year as i32, month, date_time.day)
+            .map_err(|_| out_of_range(date_time))?;
+        let time = time::Time::from_hms_micro(
+            date_time.hour,
+            date_time.minute,
+            date_time.second,
+            date_time.microsecond,
+        )
+        .map_err(|_| out_of_range(date_time))?;
+        let offset = time::UtcOffset::from_whole_seconds(date_time.offset as i32 * 60)
+            .map_err(|_| out_of_range(date_time))?;
+        Ok(time::PrimitiveDateTime::new(date, time).assume_offset(offset))
+    }
+}
+
+#[cfg(any(feature = "chrono", feature = "time"))]
+fn out_of_range(date_time: DateTime) -> Error {
+    Error::new(ErrorKind::BadData).with_value("a datetime", date_time.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{from_bytes, to_amp};
+
+    #[test]
+    fn test_bytes_not_utf8() {
+        let mut value = BTreeMap::new();
+        value.insert("a", Bytes(vec![0xff, 0xfe]));
+        let serialized = to_amp(&value).unwrap();
+        assert_eq!(value, from_bytes(&serialized).unwrap());
+        assert!(from_bytes::<BTreeMap<String, Unicode>>(&serialized).is_err());
+    }
+
+    #[test]
+    fn test_unicode_bad_utf8() {
+        let mut value = BTreeMap::new();
+        value.insert("a", Bytes(vec![0xc3, 0x28]));
+        let serialized = to_amp(&value).unwrap();
+        let error = from_bytes::<BTreeMap<String, Unicode>>(&serialized).unwrap_err();
+        assert_eq!(&ErrorKind::BadData, error.kind());
+        assert_eq!(Some("a"), error.key());
+    }
+
+    #[test]
+    fn test_path() {
+        let mut value = BTreeMap::new();
+        value.insert("a", Path::from(PathBuf::from("/tmp/caf\u{e9}")));
+        let serialized = to_amp(&value).unwrap();
+        let strings: BTreeMap<String, String> = from_bytes(&serialized).unwrap();
+        assert_eq!("/tmp/caf\u{e9}", strings["a"]);
+        assert_eq!(value, from_bytes(&serialized).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        #[derive(serde::Serialize)]
+        struct TestStruct {
+            path: Path,
+        }
+
+        let value = TestStruct {
+            path: Path::from(path::Path::new(OsStr::from_bytes(b"/tmp/caf\xe9"))),
+        };
+        let error = to_amp(&value).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Message(_)));
+        assert_eq!(Some("path"), error.key());
+        assert!(serde_json::to_string(&value).is_err());
+
+        let mut value = std::collections::BTreeMap::new();
+        value.insert(
+            Path::from(path::Path::new(OsStr::from_bytes(b"caf\xe9"))),
+            "1",
+        );
+        let error = to_amp(&value).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::Message(_)));
+    }
+
+    #[test]
+    fn test_decimal_to_string() {
+        // As written by Python's `str(decimal.Decimal(...))`.
+        let decimals = [
+            ("1.10", "1.10"),
+            ("1e2", "1E+2"),
+            ("0.0000001", "1E-7"),
+            ("0.000001", "0.000001"),
+            ("-0", "-0"),
+            ("inf", "Infinity"),
+            ("-Infinity", "-Infinity"),
+            ("NaN", "NaN"),
+            ("sNaN", "sNaN"),
+            ("NaN012", "NaN12"),
+            ("-nan", "-NaN"),
+            ("123.456E+3", "123456"),
+            ("0E-10", "0E-10"),
+            ("1.23E+5", "1.23E+5"),
+            ("-0.00", "-0.00"),
+            (
+                "12345678901234567890.123456789",
+                "12345678901234567890.123456789",
+            ),
+            ("0.1e-5", "0.000001"),
+            (".5", "0.5"),
+            ("5.", "5"),
+            ("1E+400", "1E+400"),
+        ];
+        for (string, expected) in decimals {
+            let decimal: Decimal = string.parse().unwrap();
+            assert_eq!(expected, decimal.to_string());
+        }
+    }
+
+    #[test]
+    fn test_decimal_special_values() {
+        let decimal: Decimal = "-Infinity".parse().unwrap();
+        assert!(decimal.is_infinite());
+        assert!(decimal.is_sign_negative());
+
+        let decimal: Decimal = "sNaN".parse().unwrap();
+        assert!(decimal.is_nan());
+        assert!(decimal.is_signaling_nan());
+        assert!(!decimal.is_finite());
+    }
+
+    #[test]
+    fn test_decimal_bad_data() {
+        for string in ["", ".", "1.2.3", "1e", "e5", "abc", "NaNx", "1 "] {
+            let error = string.parse::<Decimal>().unwrap_err();
+            assert_eq!(&ErrorKind::BadData, error.kind());
+        }
+    }
+
+    #[cfg(feature = "rust_decimal")]
+    #[test]
+    fn test_rust_decimal() {
+        let decimal: Decimal = "-1.10".parse().unwrap();
+        let converted = rust_decimal::Decimal::try_from(decimal.clone()).unwrap();
+        assert_eq!("-1.10", converted.to_string());
+        assert_eq!(decimal, Decimal::from(converted));
+
+        let decimal: Decimal = "1.5E+3".parse().unwrap();
+        let converted = rust_decimal::Decimal::try_from(decimal).unwrap();
+        assert_eq!("1500", converted.to_string());
+
+        for string in ["NaN", "Infinity", "1E+400", "1E-40"] {
+            let decimal: Decimal = string.parse().unwrap();
+            assert!(rust_decimal::Decimal::try_from(decimal).is_err());
+        }
+    }
+
+    #[test]
+    fn test_date_time() {
+        let date_time: DateTime = "2012-01-23T12:34:56.054321-05:30".parse().unwrap();
+        assert_eq!(2012, date_time.year());
+        assert_eq!(1, date_time.month());
+        assert_eq!(23, date_time.day());
+        assert_eq!(12, date_time.hour());
+        assert_eq!(34, date_time.minute());
+        assert_eq!(56, date_time.second());
+        assert_eq!(54321, date_time.microsecond());
+        assert_eq!(-330, date_time.offset_minutes());
+        assert_eq!("2012-01-23T12:34:56.054321-05:30", date_time.to_string());
+    }
+
+    #[test]
+    fn test_date_time_utc() {
+        // Twisted writes UTC with a `-`, but reads either.
+        let date_time: DateTime = "9876-01-23T12:34:56.000000+00:00".parse().unwrap();
+        assert_eq!(0, date_time.offset_minutes());
+        assert_eq!("9876-01-23T12:34:56.000000-00:00", date_time.to_string());
+    }
+
+    #[test]
+    fn test_date_time_bad_data() {
+        let strings = [
+            "2012-01-23T12:34:56.054321",
+            "2012-01-23T12:34:56.054321Z",
+            "2012-01-23T12:34:56+05:30",
+            "2012-01-23 12:34:56.054321+05:30",
+            "2012-02-30T12:34:56.054321+05:30",
+            "2011-02-29T12:34:56.054321+05:30",
+            "0000-01-23T12:34:56.054321+05:30",
+            "2012-01-23T24:00:00.000000+05:30",
+            "2012-01-23T23:59:60.000000+05:30",
+            "2012-01-23T12:34:56.054321+24:00",
+            "2012-01-23T12:34:56.054321+01:99",
+            "2012-01-23T12:34:56.05432a+05:30",
+        ];
+        for string in strings {
+            let error = string.parse::<DateTime>().unwrap_err();
+            assert_eq!(&ErrorKind::BadData, error.kind());
+        }
+        assert!("2012-02-29T12:34:56.054321+05:30"
+            .parse::<DateTime>()
+            .is_ok());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono() {
+        let date_time: DateTime = "2012-01-23T12:34:56.054321-05:30".parse().unwrap();
+        let converted = chrono::DateTime::<chrono::FixedOffset>::try_from(date_time).unwrap();
+        assert_eq!("2012-01-23T12:34:56.054321-05:30", converted.to_rfc3339());
+        assert_eq!(date_time, DateTime::try_from(converted).unwrap());
+
+        let utc = chrono::DateTime::from_timestamp(1_000_000_000, 123_456_789).unwrap();
+        assert_eq!(
+            "2001-09-09T01:46:40.123456-00:00",
+            DateTime::try_from(utc).unwrap().to_string()
+        );
+
+        let offset = chrono::FixedOffset::east_opt(30).unwrap();
+        assert!(DateTime::try_from(utc.with_timezone(&offset)).is_err());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time() {
+        let date_time: DateTime = "2012-01-23T12:34:56.054321+05:30".parse().unwrap();
+        let converted = time::OffsetDateTime::try_from(date_time).unwrap();
+        assert_eq!(54321, converted.microsecond());
+        assert_eq!(5 * 3600 + 30 * 60, converted.offset().whole_seconds());
+        assert_eq!(date_time, DateTime::try_from(converted).unwrap());
+
+        let utc = time::OffsetDateTime::from_unix_timestamp(-62_135_596_801).unwrap();
+        assert!(DateTime::try_from(utc).is_err());
+    }
+}